@@ -0,0 +1,134 @@
+//! `art_output_path`: keeps the current track's cover (`mpris:artUrl`) at a fixed path, for
+//! status bars and scripts that show album art by watching a file.
+//!
+//! `file://` covers are read directly and `http(s)://` ones fetched with `curl`, in the
+//! background so a slow server never holds up the worker; any other `mpris:artUrl`, a plain
+//! path included, counts as no cover, as do covers over 16 MiB. Covers are rewritten as PNG,
+//! whether they come as PNG, JPEG or WebP; a cover that can't be decoded is left out rather
+//! than written as something that isn't a PNG. The file is removed while nothing is playing.
+
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::write_atomic;
+
+/// The largest cover read or downloaded, in bytes.
+const MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+pub struct ArtWriter {
+    path: PathBuf,
+    /// The cover wanted at `path`; a download that finishes after the track changed again is
+    /// thrown away.
+    wanted: Arc<Mutex<Option<String>>>,
+}
+
+impl ArtWriter {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            wanted: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Shows the cover at `url`, or clears it for `None`. Does nothing if it is already shown.
+    pub fn update(&self, url: Option<&str>) {
+        let mut wanted = self.wanted.lock().unwrap();
+        if wanted.as_deref() == url {
+            return;
+        }
+        *wanted = url.map(str::to_string);
+        drop(wanted);
+
+        let Some(url) = url.map(str::to_string) else {
+            match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    eprintln!("Failed to remove {}: {}", self.path.display(), e);
+                }
+                _ => {}
+            }
+            return;
+        };
+        let path = self.path.clone();
+        let wanted = Arc::clone(&self.wanted);
+        thread::spawn(move || {
+            let Some(bytes) = fetch(&url) else {
+                eprintln!("Failed to load cover art from {}", url);
+                return;
+            };
+            let Some(png) = to_png(&bytes) else {
+                eprintln!("Failed to decode cover art from {}", url);
+                return;
+            };
+            // Hold the lock while writing, so a newer cover can't be overwritten by this one.
+            let wanted = wanted.lock().unwrap();
+            if wanted.as_deref() == Some(url.as_str()) {
+                if let Err(e) = write_atomic(&path, png) {
+                    eprintln!("Failed to write {}: {}", path.display(), e);
+                }
+            }
+        });
+    }
+}
+
+fn fetch(url: &str) -> Option<Vec<u8>> {
+    if let Some(path) = url.strip_prefix("file://") {
+        let path = decode_path(path);
+        if fs::metadata(&path).ok()?.len() > MAX_SIZE {
+            return None;
+        }
+        return fs::read(path).ok();
+    }
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return None;
+    }
+    let output = Command::new("curl")
+        .args(["--silent", "--fail", "--location", "--max-time", "10", "--max-filesize"])
+        .arg(MAX_SIZE.to_string())
+        .arg(url)
+        .output()
+        .ok()?;
+    // `--max-filesize` can't stop a download whose size the server doesn't say up front.
+    let fits = output.stdout.len() as u64 <= MAX_SIZE;
+    (output.status.success() && fits).then_some(output.stdout)
+}
+
+/// Undoes the percent-encoding of a `file://` URL's path.
+fn decode_path(path: &str) -> PathBuf {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex
+            .filter(|_| bytes[i] == b'%')
+            .map(|hex| u8::from_str_radix(hex, 16))
+        {
+            Some(Ok(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    Path::new(&*String::from_utf8_lossy(&decoded)).to_path_buf()
+}
+
+fn to_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(bytes).ok()?;
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .ok()?;
+    Some(png)
+}
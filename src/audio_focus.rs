@@ -0,0 +1,135 @@
+//! Works out which MPRIS players are actually outputting audio, from the sound server's sink
+//! inputs as listed by `pactl`. This is the PulseAudio interface: PipeWire is only reached
+//! through pipewire-pulse, and never queried natively, so `pactl` must be installed either way.
+//! The listing is reused for a discovery tick, so `pactl` runs at most once per tick.
+//!
+//! A sink input is matched to a player when it belongs to the process owning the player's bus
+//! name, or to a process with the same executable name (browsers play audio from a helper
+//! process).
+
+use std::{
+    fs,
+    process::Command,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use zbus::blocking::{Connection, Proxy};
+
+/// How long a listing of the sink inputs is reused: about one discovery tick, however often
+/// discovery runs in it.
+const LISTING_LIFETIME: Duration = Duration::from_secs(1);
+
+/// The unmuted streams `pactl` listed last, and when.
+static LISTED: Mutex<Option<(Instant, Vec<AudioClient>)>> = Mutex::new(None);
+
+/// A stream that is currently playing (not corked) on the sound server.
+pub struct AudioClient {
+    pub pid: Option<u32>,
+    pub binary: Option<String>,
+    pub muted: bool,
+}
+
+/// Lists the sound server's uncorked sink inputs. Empty when `pactl` isn't available.
+pub fn active_clients() -> Vec<AudioClient> {
+    let output = match Command::new("pactl")
+        .args(["list", "sink-inputs"])
+        .env("LC_ALL", "C")
+        .output()
+    {
+        Ok(output) if output.status.success() => output.stdout,
+        _ => return Vec::new(),
+    };
+
+    let mut clients = Vec::new();
+    let mut current: Option<(AudioClient, bool)> = None;
+    for line in String::from_utf8_lossy(&output).lines() {
+        let line = line.trim();
+        if line.starts_with("Sink Input #") {
+            clients.extend(current.take().filter(|(_, corked)| !corked).map(|(c, _)| c));
+            let client = AudioClient {
+                pid: None,
+                binary: None,
+                muted: false,
+            };
+            current = Some((client, false));
+            continue;
+        }
+        let Some((client, corked)) = current.as_mut() else {
+            continue;
+        };
+        if let Some(value) = line.strip_prefix("Corked:") {
+            *corked = value.trim() == "yes";
+        } else if let Some(value) = line.strip_prefix("Mute:") {
+            client.muted = value.trim() == "yes";
+        } else if let Some(value) = property(line, "application.process.id") {
+            client.pid = value.parse().ok();
+        } else if let Some(value) = property(line, "application.process.binary") {
+            client.binary = Some(value.to_string());
+        }
+    }
+    clients.extend(current.filter(|(_, corked)| !corked).map(|(c, _)| c));
+    clients
+}
+
+/// Parses a `key = "value"` property line.
+fn property<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let value = line.strip_prefix(key)?.trim_start().strip_prefix('=')?;
+    Some(value.trim().trim_matches('"'))
+}
+
+/// Returns the PID of the process owning `service_name` on the bus.
+fn owner_pid(connection: &Connection, service_name: &str) -> Option<u32> {
+    Proxy::new(
+        connection,
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+    )
+    .and_then(|proxy| proxy.call("GetConnectionUnixProcessID", &(service_name,)))
+    .ok()
+}
+
+fn process_name(pid: u32) -> Option<String> {
+    fs::read_to_string(format!("/proc/{}/comm", pid))
+        .ok()
+        .map(|name| name.trim().to_string())
+}
+
+/// The streams in `clients` that come from the process behind `service_name`.
+pub fn player_clients<'a>(
+    connection: &Connection,
+    service_name: &str,
+    clients: &'a [AudioClient],
+) -> Vec<&'a AudioClient> {
+    let Some(pid) = owner_pid(connection, service_name) else {
+        return Vec::new();
+    };
+    let name = process_name(pid);
+    clients
+        .iter()
+        .filter(|client| {
+            client.pid == Some(pid)
+                || (client.binary.is_some() && client.binary.as_ref() == name.as_ref())
+        })
+        .collect()
+}
+
+/// The subset of `players` with an unmuted stream currently playing, in `players` order.
+pub fn focused_players(connection: &Connection, players: &[String]) -> Vec<String> {
+    let mut listed = LISTED.lock().unwrap();
+    let clients = match &mut *listed {
+        Some((at, clients)) if at.elapsed() < LISTING_LIFETIME => clients,
+        listed => {
+            let clients = active_clients().into_iter().filter(|c| !c.muted).collect();
+            &listed.insert((Instant::now(), clients)).1
+        }
+    };
+    if clients.is_empty() {
+        return Vec::new();
+    }
+    players
+        .iter()
+        .filter(|player| !player_clients(connection, player, clients).is_empty())
+        .cloned()
+        .collect()
+}
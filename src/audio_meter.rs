@@ -0,0 +1,71 @@
+//! Peak-level meter fed from the default PulseAudio/PipeWire monitor source.
+//!
+//! Samples are read from `parec`, so the meter simply stays silent when it isn't installed.
+
+use std::{
+    io::Read,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+/// 50ms of mono 16-bit samples at 8kHz.
+const CHUNK_BYTES: usize = 800;
+
+pub struct AudioMeter {
+    level: Arc<AtomicU32>,
+}
+
+impl AudioMeter {
+    pub fn spawn() -> Self {
+        let level = Arc::new(AtomicU32::new(0));
+        let level_clone = Arc::clone(&level);
+        thread::spawn(move || {
+            let child = Command::new("parec")
+                .args([
+                    "--raw",
+                    "--format=s16le",
+                    "--channels=1",
+                    "--rate=8000",
+                    "--latency-msec=50",
+                    "-d",
+                    "@DEFAULT_MONITOR@",
+                ])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn();
+            let mut child = match child {
+                Ok(c) => c,
+                Err(e) => {
+                    eprintln!("Audio meter unavailable (failed to run parec): {}", e);
+                    return;
+                }
+            };
+            let Some(mut stdout) = child.stdout.take() else {
+                return;
+            };
+
+            let mut buf = [0u8; CHUNK_BYTES];
+            while stdout.read_exact(&mut buf).is_ok() {
+                let peak = buf
+                    .chunks_exact(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]).unsigned_abs())
+                    .max()
+                    .unwrap_or(0);
+                let peak = peak as f32 / i16::MAX as f32;
+                level_clone.store(peak.min(1.0).to_bits(), Ordering::Relaxed);
+            }
+            level_clone.store(0, Ordering::Relaxed);
+            let _ = child.wait();
+        });
+        Self { level }
+    }
+
+    /// The most recent peak level in `0.0..=1.0`.
+    pub fn level(&self) -> f32 {
+        f32::from_bits(self.level.load(Ordering::Relaxed))
+    }
+}
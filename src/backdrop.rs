@@ -0,0 +1,37 @@
+//! Asks DWM to draw a blurred (acrylic) backdrop behind the window. Windows 11 22H2 and later
+//! honor this; older versions ignore the attribute. Other platforms leave blur to compositor
+//! rules (see the README), so this module is only built on Windows.
+
+use raw_window_handle::{HasWindowHandle, RawWindowHandle};
+use std::ffi::c_void;
+
+const DWMWA_SYSTEMBACKDROP_TYPE: u32 = 38;
+/// The acrylic backdrop used by transient windows such as flyouts.
+const DWMSBT_TRANSIENTWINDOW: i32 = 3;
+
+#[link(name = "dwmapi")]
+extern "system" {
+    fn DwmSetWindowAttribute(hwnd: isize, attribute: u32, value: *const c_void, size: u32) -> i32;
+}
+
+pub fn request_blur(cc: &eframe::CreationContext<'_>) {
+    let Ok(handle) = cc.window_handle() else {
+        return;
+    };
+    let RawWindowHandle::Win32(handle) = handle.as_raw() else {
+        return;
+    };
+    let value = DWMSBT_TRANSIENTWINDOW;
+    // Safety: `hwnd` is the live window eframe just created, and `value` outlives the call.
+    let result = unsafe {
+        DwmSetWindowAttribute(
+            handle.hwnd.get(),
+            DWMWA_SYSTEMBACKDROP_TYPE,
+            &value as *const i32 as *const c_void,
+            std::mem::size_of::<i32>() as u32,
+        )
+    };
+    if result < 0 {
+        eprintln!("Failed to request a blurred backdrop (HRESULT {:#x})", result);
+    }
+}
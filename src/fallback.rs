@@ -0,0 +1,362 @@
+//! `fallback_command`: a script to ask for the track while no MPRIS player is playing, for CLI
+//! tools and hardware players that can only be reached some other way.
+//!
+//! The command is run with `sh -c` every `fallback_interval_ms`, on a thread of its own so a
+//! slow command never holds up the worker, and what it prints is shown until the next run. It
+//! may print a JSON object such as `{"title": "…", "artist": ["…"], "album": "…", "player": "…"}`,
+//! whose `title`, `artist`, `player` and format token names are used and the rest ignored, or a
+//! plain line, which is shown as the title. Printing nothing (or failing) means nothing is playing.
+
+use std::{
+    collections::HashMap,
+    process::{Command, Stdio},
+    sync::{mpsc::Sender, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{sticky, worker::WorkerEvent, NowPlaying};
+
+/// How long the command may run before it is killed and counted as printing nothing.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Fallback {
+    command: Arc<str>,
+    interval: Duration,
+    /// Told to `Refresh` when the command prints something new, so it shows straight away.
+    worker: Sender<WorkerEvent>,
+    /// When the command last started, and what it printed last. Shared with the thread
+    /// running it.
+    last: Arc<Mutex<Last>>,
+}
+
+#[derive(Default)]
+struct Last {
+    started: Option<Instant>,
+    running: bool,
+    output: String,
+}
+
+impl Fallback {
+    pub fn new(command: String, interval: Duration, worker: Sender<WorkerEvent>) -> Self {
+        Self {
+            command: command.into(),
+            interval,
+            worker,
+            last: Arc::default(),
+        }
+    }
+
+    /// The track the command last reported, starting it again in the background once the
+    /// interval is up.
+    pub fn current(&self) -> Option<NowPlaying> {
+        let mut last = self.last.lock().unwrap();
+        let stale = last
+            .started
+            .is_none_or(|started| started.elapsed() >= self.interval);
+        if stale && !last.running {
+            last.started = Some(Instant::now());
+            last.running = true;
+            let command = Arc::clone(&self.command);
+            let shared = Arc::clone(&self.last);
+            let worker = self.worker.clone();
+            thread::spawn(move || {
+                let output = run(&command).unwrap_or_default();
+                let mut last = shared.lock().unwrap();
+                last.running = false;
+                if last.output != output {
+                    last.output = output;
+                    let _ = worker.send(WorkerEvent::Refresh);
+                }
+            });
+        }
+        parse(&last.output)
+    }
+}
+
+/// Runs `command`, returning its output if it succeeds within the [`TIMEOUT`].
+fn run(command: &str) -> Option<String> {
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| eprintln!("Failed to run fallback_command: {}", e))
+        .ok()?;
+    let started = Instant::now();
+    while child.try_wait().ok()?.is_none() {
+        if started.elapsed() >= TIMEOUT {
+            eprintln!("fallback_command took longer than {:?}, killing it", TIMEOUT);
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+fn parse(output: &str) -> Option<NowPlaying> {
+    let output = output.trim();
+    let mut values = if output.starts_with('{') {
+        parse_object(output)?
+    } else {
+        let title = output.lines().next()?.trim();
+        HashMap::from([("title".to_string(), title.to_string())])
+    };
+    let title = values.remove("title").filter(|title| !title.is_empty())?;
+    let artist = values.remove("artist").unwrap_or_default();
+    let player = values.remove("player").unwrap_or_default();
+    let fields = values
+        .into_iter()
+        .filter_map(|(name, value)| Some((sticky::token(&name)?, value)))
+        .collect();
+    Some(NowPlaying {
+        title,
+        artist,
+        player,
+        fields,
+        position: None,
+        lyrics: None,
+        art_url: None,
+        playing: true,
+        left_off_at: None,
+    })
+}
+
+/// Reads a JSON object into its values as text: strings as they are, numbers and booleans as
+/// written, and arrays joined with ", ". `null`s and nested objects are left out. Returns `None`
+/// if it isn't valid JSON, or anything but whitespace follows it.
+fn parse_object(json: &str) -> Option<HashMap<String, String>> {
+    let mut chars = json.chars().peekable();
+    let values = parse_members(&mut chars)?;
+    skip_space(&mut chars).is_none().then_some(values)
+}
+
+fn parse_members(chars: &mut Chars) -> Option<HashMap<String, String>> {
+    let mut values = HashMap::new();
+    expect(chars, '{')?;
+    if skip_space(chars)? == '}' {
+        chars.next();
+        return Some(values);
+    }
+    loop {
+        skip_space(chars);
+        let key = parse_string(chars)?;
+        expect(chars, ':')?;
+        if let Some(value) = parse_value(chars)? {
+            values.insert(key, value);
+        }
+        match skip_space(chars)? {
+            ',' => chars.next(),
+            '}' => {
+                chars.next();
+                return Some(values);
+            }
+            _ => return None,
+        };
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+/// Skips whitespace, returning the next character without consuming it.
+fn skip_space(chars: &mut Chars) -> Option<char> {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    chars.peek().copied()
+}
+
+fn expect(chars: &mut Chars, expected: char) -> Option<()> {
+    skip_space(chars);
+    chars.next_if_eq(&expected).map(|_| ())
+}
+
+/// A value, or `Some(None)` for one that is skipped.
+fn parse_value(chars: &mut Chars) -> Option<Option<String>> {
+    match skip_space(chars)? {
+        '"' => parse_string(chars).map(Some),
+        '[' => {
+            chars.next();
+            let mut items = Vec::new();
+            if skip_space(chars)? != ']' {
+                loop {
+                    items.extend(parse_value(chars)?);
+                    match skip_space(chars)? {
+                        ',' => chars.next(),
+                        ']' => break,
+                        _ => return None,
+                    };
+                }
+            }
+            chars.next();
+            Some((!items.is_empty()).then(|| items.join(", ")))
+        }
+        '{' => parse_members(chars).map(|_| None),
+        _ => {
+            let mut literal = String::new();
+            let in_literal = |c: &char| !matches!(c, ',' | '}' | ']') && !c.is_whitespace();
+            while let Some(c) = chars.next_if(in_literal) {
+                literal.push(c);
+            }
+            match literal.as_str() {
+                "null" => Some(None),
+                "true" | "false" => Some(Some(literal)),
+                _ => literal.parse::<f64>().ok().map(|_| Some(literal)),
+            }
+        }
+    }
+}
+
+fn parse_string(chars: &mut Chars) -> Option<String> {
+    chars.next_if_eq(&'"')?;
+    let mut text = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(text),
+            '\\' => match chars.next()? {
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                'r' => text.push('\r'),
+                'b' => text.push('\u{8}'),
+                'f' => text.push('\u{c}'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16).ok()?;
+                    // Surrogate pairs come out as the replacement character.
+                    text.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                c => text.push(c),
+            },
+            c => text.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(json: &str) -> Option<Vec<(String, String)>> {
+        let mut values: Vec<_> = parse_object(json)?.into_iter().collect();
+        values.sort();
+        Some(values)
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn flat_objects_are_read_as_text() {
+        let cases = [
+            ("{}", pairs(&[])),
+            (" { } ", pairs(&[])),
+            (
+                r#"{"title": "Song", "album":"Album"}"#,
+                pairs(&[("album", "Album"), ("title", "Song")]),
+            ),
+            (
+                r#"{"artist": ["A", "B"], "genre": []}"#,
+                pairs(&[("artist", "A, B")]),
+            ),
+            (
+                r#"{"n": 3, "x": -1.5e2, "ok": true, "no": false, "gone": null}"#,
+                pairs(&[("n", "3"), ("no", "false"), ("ok", "true"), ("x", "-1.5e2")]),
+            ),
+            (
+                r#"{"artist": ["A", null, 2]}"#,
+                pairs(&[("artist", "A, 2")]),
+            ),
+        ];
+        for (json, expected) in cases {
+            assert_eq!(object(json), expected, "{}", json);
+        }
+    }
+
+    #[test]
+    fn escapes_are_decoded() {
+        let cases = [
+            (r#"{"t": "say \"hi\""}"#, "say \"hi\""),
+            (r#"{"t": "a\\b\/c"}"#, "a\\b/c"),
+            (r#"{"t": "line\nnext\ttab"}"#, "line\nnext\ttab"),
+            (r#"{"t": "caf\u00e9 \u00C9"}"#, "café É"),
+            (r#"{"t": "\ud83c"}"#, "\u{fffd}"),
+        ];
+        for (json, expected) in cases {
+            assert_eq!(object(json), pairs(&[("t", expected)]), "{}", json);
+        }
+        assert_eq!(object(r#"{"t": "\u00g0"}"#), None);
+        assert_eq!(object(r#"{"t": "open"#), None);
+    }
+
+    #[test]
+    fn nested_objects_are_skipped() {
+        let cases = [
+            (r#"{"a": {"b": "c"}, "t": "Song"}"#, pairs(&[("t", "Song")])),
+            (
+                r#"{"a": {"b": {"c": [1, {}]}}, "t": "Song"}"#,
+                pairs(&[("t", "Song")]),
+            ),
+            (
+                r#"{"a": [{"b": 1}, "x", [1, 2]], "t": "Song"}"#,
+                pairs(&[("a", "x, 1, 2"), ("t", "Song")]),
+            ),
+            (r#"{"a": {"b": }, "t": "Song"}"#, None),
+        ];
+        for (json, expected) in cases {
+            assert_eq!(object(json), expected, "{}", json);
+        }
+    }
+
+    #[test]
+    fn invalid_json_and_trailing_garbage_are_rejected() {
+        for json in [
+            r#"{"t": "Song"} trailing"#,
+            r#"{"t": "Song"}}"#,
+            r#"{"t": "Song""#,
+            r#"{"t" "Song"}"#,
+            r#"{"t": "Song",}"#,
+            r#"{"t": Song}"#,
+            r#"{t: "Song"}"#,
+        ] {
+            assert_eq!(object(json), None, "{}", json);
+        }
+    }
+
+    #[test]
+    fn output_is_read_as_json_or_a_plain_line() {
+        let json =
+            parse(r#"{"title": "Song", "artist": "Artist", "album": "Album", "x": "y"}"#).unwrap();
+        assert_eq!(
+            (json.title.as_str(), json.artist.as_str()),
+            ("Song", "Artist")
+        );
+        assert_eq!(json.fields.get("album").map(String::as_str), Some("Album"));
+        assert_eq!(json.fields.len(), 1);
+
+        let line = parse("  Song - Artist  \nsecond line\n").unwrap();
+        assert_eq!(
+            (line.title.as_str(), line.artist.as_str()),
+            ("Song - Artist", "")
+        );
+
+        for nothing in [
+            "",
+            "\n",
+            r#"{"artist": "Artist"}"#,
+            r#"{"title": ""}"#,
+            "{oops",
+        ] {
+            assert!(parse(nothing).is_none(), "{:?}", nothing);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Best-effort detection of a fullscreen window, so the widget can stop covering it.
+//!
+//! This reads the EWMH `_NET_ACTIVE_WINDOW` / `_NET_WM_STATE` properties with `xprop`, so it
+//! only works on X11 window managers that maintain them (and on XWayland for X11 clients).
+//! Elsewhere nothing is ever reported as fullscreen.
+
+use std::{
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Starts a thread that keeps the returned flag set while the active window is fullscreen.
+pub fn spawn_watcher() -> Arc<AtomicBool> {
+    let fullscreen = Arc::new(AtomicBool::new(false));
+    let fullscreen_clone = Arc::clone(&fullscreen);
+    thread::spawn(move || loop {
+        let active = xprop(&["-root", "_NET_ACTIVE_WINDOW"]);
+        let Some(active) = active else {
+            eprintln!("hide_over_fullscreen: xprop unavailable, fullscreen detection disabled");
+            return;
+        };
+        // e.g. "_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007"
+        let is_fullscreen = active
+            .rsplit(' ')
+            .next()
+            .filter(|id| id.starts_with("0x") && *id != "0x0")
+            .and_then(|id| xprop(&["-id", id, "_NET_WM_STATE"]))
+            .is_some_and(|state| state.contains("_NET_WM_STATE_FULLSCREEN"));
+        fullscreen_clone.store(is_fullscreen, Ordering::Relaxed);
+        thread::sleep(CHECK_INTERVAL);
+    });
+    fullscreen
+}
+
+fn xprop(args: &[&str]) -> Option<String> {
+    let output = Command::new("xprop").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
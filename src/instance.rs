@@ -0,0 +1,112 @@
+//! Single-instance guard. The window owns a well-known bus name, so a second launch (say from a
+//! doubled-up autostart entry) exits instead of stacking another overlay on the first.
+//!
+//! `--replace` takes the name over, which tells the running instance to quit; `--kill` does the
+//! same and then exits too. The bus drops the name when its owner exits, so there is no lock file
+//! to clean up, even after a crash. Each `--profile` gets its own name, so one instance per
+//! profile (e.g. one per monitor) can still run side by side.
+
+use std::thread;
+use zbus::{
+    blocking::{fdo::DBusProxy, Connection, MessageIterator},
+    fdo::{RequestNameFlags, RequestNameReply},
+    message::Type,
+    MatchRule,
+};
+
+const BUS_NAME: &str = "io.github.ndrake.DbusNowPlaying";
+
+/// Outcome of [`claim`].
+pub enum Claim {
+    /// This is the only instance; keep the connection alive for as long as the window is open.
+    Owned(Connection),
+    /// Another instance already owns the name.
+    Running,
+}
+
+/// Claims the instance name for `profile`, taking it from a running instance when `replace` is
+/// set. Whoever owns the name exits as soon as another instance takes it over.
+pub fn claim(profile: Option<&str>, replace: bool) -> zbus::Result<Claim> {
+    let name = bus_name(profile);
+    let connection = Connection::session()?;
+    let mut flags = RequestNameFlags::AllowReplacement | RequestNameFlags::DoNotQueue;
+    if replace {
+        flags |= RequestNameFlags::ReplaceExisting;
+    }
+    // Subscribe before owning the name, so a replacement can't slip in unnoticed.
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .sender("org.freedesktop.DBus")?
+        .interface("org.freedesktop.DBus")?
+        .member("NameLost")?
+        .build();
+    let lost = MessageIterator::for_match_rule(rule, &connection, None)?;
+    match connection.request_name_with_flags(name.as_str(), flags) {
+        Ok(RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner) => {}
+        Ok(RequestNameReply::Exists | RequestNameReply::InQueue) | Err(zbus::Error::NameTaken) => {
+            return Ok(Claim::Running)
+        }
+        Err(e) => return Err(e),
+    }
+
+    thread::spawn(move || {
+        for message in lost.flatten() {
+            let Ok(lost_name) = message.body().deserialize::<String>() else {
+                continue;
+            };
+            if lost_name == name {
+                eprintln!("Told to quit by --replace or --kill, exiting.");
+                std::process::exit(0);
+            }
+        }
+    });
+    Ok(Claim::Owned(connection))
+}
+
+/// `--kill`: makes the instance for `profile` quit, if one is running. Returns the exit code.
+pub fn kill(profile: Option<&str>) -> i32 {
+    let name = bus_name(profile);
+    let result = Connection::session().and_then(|connection| {
+        let running = DBusProxy::new(&connection)?.name_has_owner(name.as_str().try_into()?)?;
+        if !running {
+            return Ok(None);
+        }
+        let flags = RequestNameFlags::ReplaceExisting | RequestNameFlags::DoNotQueue;
+        connection
+            .request_name_with_flags(name.as_str(), flags)
+            .map(Some)
+    });
+    // The name is released again when this process exits.
+    match result {
+        Ok(None) => {
+            println!("No instance is running.");
+            0
+        }
+        Ok(Some(RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner)) => {
+            println!("Stopped the running instance.");
+            0
+        }
+        Ok(Some(_)) | Err(zbus::Error::NameTaken) => {
+            eprintln!("The running instance could not be replaced.");
+            1
+        }
+        Err(e) => {
+            eprintln!("Failed to reach D-Bus: {}", e);
+            1
+        }
+    }
+}
+
+/// The bus name for `profile`, with characters bus names don't allow replaced by `_`.
+fn bus_name(profile: Option<&str>) -> String {
+    match profile {
+        None => BUS_NAME.to_string(),
+        Some(profile) => {
+            let element: String = profile
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            format!("{}.profile_{}", BUS_NAME, element)
+        }
+    }
+}
@@ -1,17 +1,46 @@
+mod art;
+#[cfg(feature = "audio-focus")]
+mod audio_focus;
+#[cfg(feature = "audio-meter")]
+mod audio_meter;
+#[cfg(windows)]
+mod backdrop;
+mod fallback;
+mod fullscreen;
+mod instance;
+mod monitor;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod resume;
+mod schedule;
+mod scrobble;
+mod settings;
+#[cfg(unix)]
+mod signals;
+mod sticky;
+mod tui;
+mod watch;
+mod worker;
+
 use eframe::{
     egui::{self, CentralPanel, Color32, Context, FontId, Label, RichText},
     App, NativeOptions,
 };
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     convert::TryInto,
     fs,
-    path::Path,
-    sync::{Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
+use worker::{Worker, WorkerEvent};
 use zbus::{
     blocking::{Connection, Proxy},
     zvariant::{OwnedValue, Value},
@@ -24,6 +53,85 @@ struct Config {
     bg_color: String,
     window_x: Option<i32>,
     window_y: Option<i32>,
+    idle_text: Option<String>,
+    prefix: Option<String>,
+    suffix: Option<String>,
+    affixes_when_idle: Option<bool>,
+    inhibit_idle: Option<bool>,
+    format: Option<String>,
+    audio_meter: Option<bool>,
+    corner_radius: Option<f32>,
+    border_color: Option<String>,
+    border_width: Option<f32>,
+    text_outline_color: Option<String>,
+    text_outline_width: Option<f32>,
+    no_shadow: Option<bool>,
+    max_fps: Option<u32>,
+    click_actions: Option<HashMap<String, String>>,
+    rtl: Option<bool>,
+    field_map: Option<HashMap<String, String>>,
+    force_opaque: Option<bool>,
+    follow_audio_focus: Option<bool>,
+    font_size_min: Option<f32>,
+    font_size_max: Option<f32>,
+    font_size: Option<f32>,
+    text_transform: Option<String>,
+    backdrop_blur: Option<bool>,
+    hide_over_fullscreen: Option<bool>,
+    property_retries: Option<u32>,
+    artist_color: Option<String>,
+    remember_last_track: Option<bool>,
+    state_file: Option<String>,
+    valign: Option<String>,
+    assume_playing_without_status: Option<bool>,
+    bg_image: Option<String>,
+    bg_image_mode: Option<String>,
+    bg_image_opacity: Option<f32>,
+    monitor: Option<String>,
+    normalize_whitespace: Option<bool>,
+    error_text: Option<String>,
+    error_color: Option<String>,
+    strict_player: Option<bool>,
+    mqtt_broker: Option<String>,
+    mqtt_topic: Option<String>,
+    fade_ms: Option<u32>,
+    refresh_on_show: Option<bool>,
+    progress_position: Option<String>,
+    progress_height: Option<f32>,
+    progress_color: Option<String>,
+    progress_tint: Option<bool>,
+    progress_tint_start: Option<String>,
+    progress_tint_end: Option<String>,
+    theme: Option<String>,
+    max_title_chars: Option<usize>,
+    max_artist_chars: Option<usize>,
+    ellipsis: Option<String>,
+    copy_format: Option<String>,
+    only_in_desktops: Option<Vec<String>>,
+    scrobble_log: Option<String>,
+    fade_on_hover: Option<bool>,
+    show_lyrics: Option<bool>,
+    window_title: Option<String>,
+    app_id: Option<String>,
+    show_delay_ms: Option<u64>,
+    prefer_audible: Option<bool>,
+    art_output_path: Option<String>,
+    multi_playing_strategy: Option<String>,
+    player_priority: Option<Vec<String>>,
+    no_session_bus: Option<String>,
+    eq_icon: Option<bool>,
+    paused_color: Option<String>,
+    resume_hint: Option<bool>,
+    fallback_command: Option<String>,
+    fallback_interval_ms: Option<u64>,
+    idle_backoff_after_ms: Option<u64>,
+    idle_backoff_interval_ms: Option<u64>,
+    lines: Option<Vec<LineFormat>>,
+    hide_patterns: Option<Vec<String>>,
+    hide_patterns_case_sensitive: Option<bool>,
+    schedule: Option<BTreeMap<String, Vec<String>>>,
+    media_shim: Option<String>,
+    dbus: Option<DbusNames>,
 }
 
 impl Default for Config {
@@ -34,342 +142,3490 @@ impl Default for Config {
             bg_color: "#000000".to_string(),
             window_x: Some(0),
             window_y: Some(1000),
+            idle_text: None,
+            prefix: None,
+            suffix: None,
+            affixes_when_idle: None,
+            inhibit_idle: None,
+            format: None,
+            audio_meter: None,
+            corner_radius: None,
+            border_color: None,
+            border_width: None,
+            text_outline_color: None,
+            text_outline_width: None,
+            no_shadow: None,
+            max_fps: None,
+            click_actions: None,
+            rtl: None,
+            field_map: None,
+            force_opaque: None,
+            follow_audio_focus: None,
+            font_size_min: None,
+            font_size_max: None,
+            font_size: None,
+            text_transform: None,
+            backdrop_blur: None,
+            hide_over_fullscreen: None,
+            property_retries: None,
+            artist_color: None,
+            remember_last_track: None,
+            state_file: None,
+            valign: None,
+            assume_playing_without_status: None,
+            bg_image: None,
+            bg_image_mode: None,
+            bg_image_opacity: None,
+            monitor: None,
+            normalize_whitespace: None,
+            error_text: None,
+            error_color: None,
+            strict_player: None,
+            mqtt_broker: None,
+            mqtt_topic: None,
+            fade_ms: None,
+            refresh_on_show: None,
+            progress_position: None,
+            progress_height: None,
+            progress_color: None,
+            progress_tint: None,
+            progress_tint_start: None,
+            progress_tint_end: None,
+            theme: None,
+            max_title_chars: None,
+            max_artist_chars: None,
+            ellipsis: None,
+            copy_format: None,
+            only_in_desktops: None,
+            scrobble_log: None,
+            fade_on_hover: None,
+            show_lyrics: None,
+            window_title: None,
+            app_id: None,
+            show_delay_ms: None,
+            prefer_audible: None,
+            art_output_path: None,
+            multi_playing_strategy: None,
+            player_priority: None,
+            no_session_bus: None,
+            eq_icon: None,
+            paused_color: None,
+            resume_hint: None,
+            fallback_command: None,
+            fallback_interval_ms: None,
+            idle_backoff_after_ms: None,
+            idle_backoff_interval_ms: None,
+            lines: None,
+            hide_patterns: None,
+            hide_patterns_case_sensitive: None,
+            schedule: None,
+            media_shim: None,
+            dbus: None,
+        }
+    }
+}
+
+/// One row of `lines`: a format on its own, or a table that also gives the row's alignment
+/// and the color of its plain text.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+enum LineFormat {
+    Format(String),
+    Styled {
+        format: String,
+        align: Option<String>,
+        color: Option<String>,
+    },
+}
+
+impl LineFormat {
+    fn format(&self) -> &str {
+        match self {
+            LineFormat::Format(format) | LineFormat::Styled { format, .. } => format,
+        }
+    }
+
+    fn align(&self) -> Option<&str> {
+        match self {
+            LineFormat::Format(_) => None,
+            LineFormat::Styled { align, .. } => align.as_deref(),
+        }
+    }
+
+    fn color(&self) -> Option<&str> {
+        match self {
+            LineFormat::Format(_) => None,
+            LineFormat::Styled { color, .. } => color.as_deref(),
+        }
+    }
+}
+
+/// How far across its free space a `lines` row is pushed: 0 for left, 1 for right.
+fn parse_align(name: &str) -> Option<f32> {
+    match name {
+        "left" => Some(0.0),
+        "center" => Some(0.5),
+        "right" => Some(1.0),
+        _ => None,
+    }
+}
+
+/// The `[dbus]` section: where to read the followed player's track from, for services that
+/// aren't MPRIS players. Anything left out uses the MPRIS name.
+#[derive(Deserialize, Serialize, Clone, Default)]
+struct DbusNames {
+    object_path: Option<String>,
+    interface: Option<String>,
+    /// A string property that reads "Playing" while playing, or a boolean one.
+    status_property: Option<String>,
+    /// A dictionary property holding the track's metadata.
+    metadata_property: Option<String>,
+    /// String properties to take the title and artist from instead of the metadata.
+    title_property: Option<String>,
+    artist_property: Option<String>,
+    /// A further signal that means the track changed, as `interface.member`.
+    signal: Option<String>,
+}
+
+impl DbusNames {
+    fn object_path(&self) -> &str {
+        self.object_path.as_deref().unwrap_or("/org/mpris/MediaPlayer2")
+    }
+
+    fn interface(&self) -> &str {
+        self.interface.as_deref().unwrap_or("org.mpris.MediaPlayer2.Player")
+    }
+
+    fn status_property(&self) -> &str {
+        self.status_property.as_deref().unwrap_or("PlaybackStatus")
+    }
+
+    fn metadata_property(&self) -> &str {
+        self.metadata_property.as_deref().unwrap_or("Metadata")
+    }
+
+    /// `signal` split into its interface and member.
+    fn signal(&self) -> Option<(&str, &str)> {
+        self.signal.as_deref()?.rsplit_once('.')
+    }
+
+    fn validate(&self, dbus_service: Option<&str>, errors: &mut Vec<String>) {
+        let names = [
+            &self.object_path,
+            &self.interface,
+            &self.status_property,
+            &self.metadata_property,
+            &self.title_property,
+            &self.artist_property,
+            &self.signal,
+        ];
+        if dbus_service.is_none() && names.iter().any(|name| name.is_some()) {
+            errors.push(
+                "[dbus] only applies to the player named by dbus_service, which is not set"
+                    .to_string(),
+            );
+        }
+        if zbus::zvariant::ObjectPath::try_from(self.object_path()).is_err() {
+            errors.push(format!("dbus.object_path '{}' is not an object path", self.object_path()));
+        }
+        if zbus::names::InterfaceName::try_from(self.interface()).is_err() {
+            errors.push(format!("dbus.interface '{}' is not an interface name", self.interface()));
+        }
+        // Another service's interface won't have MPRIS's `Metadata`, so say where the track is.
+        if self.interface.is_some()
+            && self.metadata_property.is_none()
+            && self.title_property.is_none()
+        {
+            errors.push(
+                "dbus.interface is overridden, so dbus.metadata_property or dbus.title_property \
+                 must be set too"
+                    .to_string(),
+            );
+        }
+        if let Some(signal) = &self.signal {
+            let valid = self.signal().is_some_and(|(interface, member)| {
+                zbus::names::InterfaceName::try_from(interface).is_ok()
+                    && zbus::names::MemberName::try_from(member).is_ok()
+            });
+            if !valid {
+                errors.push(format!(
+                    "dbus.signal '{}' must be written as interface.member",
+                    signal
+                ));
+            }
         }
     }
 }
 
 impl Config {
-    fn load() -> Self {
-        Self::load_from_file("config.toml")
-    }
-
-    fn load_from_file<P: AsRef<Path>>(path: P) -> Self {
-        match fs::read_to_string(&path) {
-            Ok(content) => {
-                match toml::from_str(&content) {
-                    Ok(config) => config,
-                    Err(e) => {
-                        eprintln!("Failed to parse config file: {}. Using defaults.", e);
-                        Self::default()
-                    }
-                }
+    /// Loads the effective config for `args`, warning about (and skipping) any bad layer.
+    fn load(args: &Args) -> Self {
+        let mut builder = ConfigBuilder::new();
+        if let Err(e) = builder.file(args.config.as_deref(), args.profile.as_deref()) {
+            eprintln!("Failed to parse config file: {}. Ignoring it.", e);
+        }
+        builder.env();
+        builder.overrides(args.overrides.clone());
+        builder.build().unwrap_or_else(|e| {
+            eprintln!("Invalid settings: {}. Using defaults.", e);
+            Self::default()
+        })
+    }
+
+    /// Parses the config and merges the selected `[profiles.<name>]` table over the base.
+    fn resolve(content: &str, profile: Option<&str>) -> Result<toml::Table, String> {
+        let mut base: toml::Table = toml::from_str(content).map_err(|e| e.to_string())?;
+        let mut profiles = match base.remove("profiles") {
+            Some(toml::Value::Table(profiles)) => profiles,
+            _ => toml::Table::new(),
+        };
+
+        if let Some(name) = profile {
+            match profiles.remove(name) {
+                Some(toml::Value::Table(overlay)) => merge_tables(&mut base, overlay),
+                _ => return Err(format!("profile '{}' not found", name)),
             }
-            Err(e) => {
-                eprintln!("Failed to read config file: {}. Using defaults.", e);
-                Self::default()
+        }
+        Ok(base)
+    }
+
+    /// Returns a description of every problem found in the config.
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let colors = [
+            ("fg_color", Some(&self.fg_color)),
+            ("bg_color", Some(&self.bg_color)),
+            ("artist_color", self.artist_color.as_ref()),
+            ("error_color", self.error_color.as_ref()),
+            ("progress_color", self.progress_color.as_ref()),
+            ("border_color", self.border_color.as_ref()),
+            ("text_outline_color", self.text_outline_color.as_ref()),
+            ("paused_color", self.paused_color.as_ref()),
+            ("progress_tint_start", self.progress_tint_start.as_ref()),
+            ("progress_tint_end", self.progress_tint_end.as_ref()),
+        ];
+        for (name, value) in colors {
+            // Missing or empty colors fall back to their defaults.
+            let Some(value) = value.filter(|value| !value.trim().is_empty()) else {
+                continue;
+            };
+            if !is_valid_color(value) {
+                errors.push(format!("{} '{}' is not a #RRGGBB or #RRGGBBAA color", name, value));
+            }
+        }
+        if let Some(format) = &self.format {
+            errors.extend(Template::parse(format).errors);
+        }
+        for (index, line) in self.lines.iter().flatten().enumerate() {
+            let name = format!("lines[{}]", index);
+            let errors_in_line = Template::parse(line.format()).errors;
+            errors.extend(errors_in_line.into_iter().map(|e| format!("{}: {}", name, e)));
+            if let Some(align) = line.align().filter(|align| parse_align(align).is_none()) {
+                errors.push(format!(
+                    "{}: align '{}' must be one of left, center, right",
+                    name, align
+                ));
+            }
+            if let Some(color) = line.color().filter(|color| !is_valid_color(color)) {
+                errors.push(format!(
+                    "{}: color '{}' is not a #RRGGBB or #RRGGBBAA color",
+                    name, color
+                ));
+            }
+        }
+        if self.hide_patterns.iter().flatten().any(|pattern| pattern.is_empty()) {
+            errors.push("hide_patterns can't contain an empty pattern".to_string());
+        }
+        for range in self.schedule.iter().flatten().map(|(range, _)| range) {
+            if schedule::parse_range(range).is_none() {
+                errors.push(format!(
+                    "schedule has invalid time range '{}' (expected HH:MM-HH:MM)",
+                    range
+                ));
+            }
+        }
+        if let Some(name) = self.media_shim_name() {
+            if zbus::names::WellKnownName::try_from(name).is_err() {
+                errors.push(format!(
+                    "media_shim '{}' must be \"playerctld\" or a D-Bus bus name",
+                    name
+                ));
+            }
+        }
+        if self.lines.as_ref().is_some_and(Vec::is_empty) {
+            errors.push("lines must list at least one format".to_string());
+        }
+        if let Some(format) = &self.copy_format {
+            let errors_in_copy = Template::parse(format).errors;
+            errors.extend(errors_in_copy.into_iter().map(|e| format!("copy_format: {}", e)));
+        }
+        if self.max_fps == Some(0) {
+            errors.push("max_fps must be at least 1".to_string());
+        }
+        for (trigger, action) in self.click_actions.iter().flatten() {
+            if !CLICK_TRIGGERS.contains(&trigger.as_str()) {
+                errors.push(format!(
+                    "click_actions has unknown trigger '{}' (expected one of {})",
+                    trigger,
+                    CLICK_TRIGGERS.join(", ")
+                ));
             }
+            if Action::parse(action).is_none() {
+                errors.push(format!("click_actions.{} has unknown action '{}'", trigger, action));
+            }
+        }
+        if let Some(dbus) = &self.dbus {
+            dbus.validate(self.dbus_service.as_deref(), &mut errors);
+        }
+        for token in self.field_map.iter().flatten().map(|(token, _)| token) {
+            if !is_known_token(token) || token == "player" {
+                errors.push(format!("field_map has unknown token '{}'", token));
+            }
+        }
+        if let Some(transform) = &self.text_transform {
+            if TextTransform::parse(transform).is_none() {
+                errors.push(format!(
+                    "text_transform '{}' must be one of none, upper, lower, title",
+                    transform
+                ));
+            }
+        }
+        if let Some(valign) = &self.valign {
+            if parse_valign(valign).is_none() {
+                errors.push(format!("valign '{}' must be one of top, center, bottom", valign));
+            }
+        }
+        if let Some(position) = &self.progress_position {
+            if ProgressPosition::parse(position).is_none() {
+                errors.push(format!(
+                    "progress_position '{}' must be one of top, bottom, background",
+                    position
+                ));
+            }
+        }
+        if let Some(name) = self.theme.as_deref().filter(|name| theme(name).is_none()) {
+            let names: Vec<&str> = THEMES.iter().map(|(name, _)| *name).collect();
+            errors.push(format!("theme '{}' must be one of {}", name, names.join(", ")));
+        }
+        if let Some(strategy) = &self.multi_playing_strategy {
+            if MultiPlaying::parse(strategy).is_none() {
+                errors.push(format!(
+                    "multi_playing_strategy '{}' must be one of first, loudest, priority, \
+                     most_recent",
+                    strategy
+                ));
+            }
+        }
+        if let Some(mode) = &self.no_session_bus {
+            if NoSessionBus::parse(mode).is_none() {
+                errors.push(format!("no_session_bus '{}' must be one of retry, error, exit", mode));
+            }
+        }
+        if self.progress_height.is_some_and(|height| height <= 0.0) {
+            errors.push("progress_height must be positive".to_string());
+        }
+        let (min, max) = self.font_size_range();
+        if min <= 0.0 || max <= 0.0 {
+            errors.push("font sizes must be positive".to_string());
+        }
+        if self.font_size.is_some_and(|size| size <= 0.0) {
+            errors.push("font_size must be positive".to_string());
+        }
+        if min > max {
+            errors.push(format!(
+                "font_size_min ({}) must not exceed font_size_max ({})",
+                min, max
+            ));
+        }
+        if self.corner_radius.is_some_and(|radius| radius < 0.0) {
+            errors.push("corner_radius must not be negative".to_string());
+        }
+        if self.border_width.is_some_and(|width| width < 0.0) {
+            errors.push("border_width must not be negative".to_string());
+        }
+        if self.text_outline_width.is_some_and(|width| width < 0.0) {
+            errors.push("text_outline_width must not be negative".to_string());
+        }
+        if let Some(mode) = &self.bg_image_mode {
+            if !matches!(mode.as_str(), "stretch" | "tile") {
+                errors.push(format!("bg_image_mode '{}' must be stretch or tile", mode));
+            }
+        }
+        if self
+            .bg_image_opacity
+            .is_some_and(|opacity| !(0.0..=1.0).contains(&opacity))
+        {
+            errors.push("bg_image_opacity must be between 0 and 1".to_string());
+        }
+        errors
+    }
+
+    /// The `(min, max)` bounds for dynamic font sizing.
+    fn font_size_range(&self) -> (f32, f32) {
+        (
+            self.font_size_min.unwrap_or(10.0),
+            self.font_size_max.unwrap_or(15.0),
+        )
+    }
+
+    /// The `format` template, with the per-field length limits applied.
+    fn template(&self) -> Template {
+        self.template_for(self.format.as_deref().unwrap_or(DEFAULT_FORMAT))
+    }
+
+    /// `format` parsed with the per-field length limits applied.
+    fn template_for(&self, format: &str) -> Template {
+        let mut template = Template::parse(format);
+        if let Some(ellipsis) = &self.ellipsis {
+            template.ellipsis = ellipsis.clone();
+        }
+        let limits = [("title", self.max_title_chars), ("artist", self.max_artist_chars)];
+        for (name, max) in limits {
+            if let Some(max) = max {
+                template.max_chars.insert(name.to_string(), max);
+            }
+        }
+        template
+    }
+
+    /// Whether anything shows the playback position, so the worker has to read it.
+    fn shows_position(&self) -> bool {
+        self.progress_position.is_some()
+            || self.progress_tint.unwrap_or(false)
+            || self.show_lyrics.unwrap_or(false)
+            || self.resume_hint.unwrap_or(false)
+            || self.uses_any_token(&["position_ms", "time"])
+    }
+
+    /// The bus name of the `media_shim` to follow, if one is set.
+    fn media_shim_name(&self) -> Option<&str> {
+        match self.media_shim.as_deref()? {
+            "playerctld" => Some("org.mpris.MediaPlayer2.playerctld"),
+            name => Some(name),
+        }
+    }
+
+    /// Whether the format shows the track's place in the player's track list, so the worker
+    /// has to read it.
+    fn shows_queue(&self) -> bool {
+        self.uses_any_token(&["queue_pos", "queue_len"])
+    }
+
+    /// Whether the `format` or any of the `lines` shows one of `tokens`.
+    fn uses_any_token(&self, tokens: &[&str]) -> bool {
+        let format = self.format.as_deref().unwrap_or(DEFAULT_FORMAT);
+        let lines = self.lines.iter().flatten().map(LineFormat::format);
+        std::iter::once(format).chain(lines).any(|format| {
+            let template = Template::parse(format);
+            tokens.iter().any(|token| template.uses_token(token))
+        })
+    }
+
+    /// Whether `only_in_desktops` allows running in the current session, going by
+    /// `$XDG_CURRENT_DESKTOP` (a colon-separated list such as `ubuntu:GNOME`).
+    fn allowed_in_current_desktop(&self) -> bool {
+        let Some(allowed) = &self.only_in_desktops else {
+            return true;
+        };
+        let current = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+        current
+            .split(':')
+            .any(|desktop| allowed.iter().any(|name| name.eq_ignore_ascii_case(desktop)))
+    }
+
+    /// Where the last track is remembered, if `remember_last_track` is on.
+    fn state_file(&self) -> Option<PathBuf> {
+        self.remember_last_track.unwrap_or(false).then(|| {
+            self.state_file
+                .as_deref()
+                .map_or_else(sticky::default_path, expand_path)
+        })
+    }
+
+    /// `fg_color`; an empty string means the default, white.
+    fn fg(&self) -> Color32 {
+        Self::parse_color_or(&self.fg_color, Color32::WHITE)
+    }
+
+    /// `bg_color`; an empty string means no background at all (black without a compositor).
+    fn bg(&self) -> Color32 {
+        Self::parse_color_or(&self.bg_color, Color32::TRANSPARENT)
+    }
+
+    /// Parses `s`, or returns `default` when it's empty or whitespace.
+    fn parse_color_or(s: &str, default: Color32) -> Color32 {
+        if s.trim().is_empty() {
+            default
+        } else {
+            Self::parse_color(s)
         }
     }
 
     fn parse_color(s: &str) -> Color32 {
-        let s = s.trim_start_matches('#');
-        if s.len() != 6 {
+        if !is_valid_color(s) {
             eprintln!("Invalid color format '{}', using white", s);
             return Color32::WHITE;
         }
+        let s = s.trim_start_matches('#');
         let r = u8::from_str_radix(&s[0..2], 16).unwrap_or(255);
         let g = u8::from_str_radix(&s[2..4], 16).unwrap_or(255);
         let b = u8::from_str_radix(&s[4..6], 16).unwrap_or(255);
-        Color32::from_rgb(r, g, b)
+        let a = s.get(6..8).map_or(255, |a| u8::from_str_radix(a, 16).unwrap_or(255));
+        Color32::from_rgba_unmultiplied(r, g, b, a)
     }
 }
 
-struct NowPlaying {
-    title: String,
-    artist: String,
-}
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+/// Prefix of the environment variables that override settings, e.g. `DBUS_NOW_PLAYING_FG_COLOR`.
+const ENV_PREFIX: &str = "DBUS_NOW_PLAYING_";
 
-struct AppState {
-    current: Option<NowPlaying>,
-}
+/// Built-in `theme` presets: config fragments that sit between the defaults and the user's
+/// own settings.
+const THEMES: &[(&str, &str)] = &[
+    (
+        "spotify",
+        r##"
+        fg_color = "#FFFFFF"
+        bg_color = "#191414"
+        artist_color = "#1DB954"
+        corner_radius = 8.0
+        progress_position = "bottom"
+        progress_color = "#1DB954"
+        "##,
+    ),
+    (
+        "minimal",
+        r##"
+        fg_color = "#FFFFFFDD"
+        bg_color = "#00000000"
+        artist_color = "#FFFFFF88"
+        format = "{title} · {artist}"
+        font_size_max = 13.0
+        text_transform = "lower"
+        "##,
+    ),
+    (
+        "solarized",
+        r##"
+        fg_color = "#93A1A1"
+        bg_color = "#002B36"
+        artist_color = "#B58900"
+        error_color = "#DC322F"
+        corner_radius = 4.0
+        "##,
+    ),
+];
 
-struct NowPlayingApp {
-    shared: Arc<Mutex<AppState>>,
-    fg_color: Color32,
-    bg_color: Color32,
+fn theme(name: &str) -> Option<toml::Table> {
+    THEMES
+        .iter()
+        .find(|(theme, _)| *theme == name)
+        .and_then(|(_, fragment)| toml::from_str(fragment).ok())
 }
 
-impl App for NowPlayingApp {
-    fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
-        CentralPanel::default()
-            .frame(egui::Frame::default().fill(self.bg_color))
-            .show(ctx, |ui| {
-                if let Some(current) = &self.shared.lock().unwrap().current {
-                    let title = &current.title;
-                    let artist = &current.artist;
-
-                    // --- Dynamic font sizing ---
-                    let max_font_size = 15.0;
-                    let min_font_size = 10.0;
-                    let padding = 10.0;
-                    let target_width = ui.available_width() - padding;
-
-                    let mut font_size = max_font_size;
-                    loop {
-                        let total_width = ctx.fonts(|fonts| {
-                            // Measure title and artist parts separately for accuracy
-                            let title_width = fonts
-                                .layout_no_wrap(
-                                    title.to_string(),
-                                    FontId::proportional(font_size),
-                                    self.fg_color,
-                                )
-                                .size()
-                                .x;
-                            let artist_width = fonts
-                                .layout_no_wrap(
-                                    format!("{}", artist), // Add the separator for measurement
-                                    FontId::proportional(font_size),
-                                    self.fg_color,
-                                )
-                                .size()
-                                .x;
-                            title_width + artist_width
-                        });
-
-                        if total_width <= target_width || font_size <= min_font_size {
-                            break;
-                        }
-                        font_size -= 1.0;
-                    }
+/// Assembles the effective [`Config`] from its sources. Each layer overrides the ones before it:
+/// built-in defaults, the `theme` preset, the config file (with the selected profile merged in),
+/// `DBUS_NOW_PLAYING_*` environment variables, then command-line flags.
+struct ConfigBuilder {
+    /// Everything but the defaults and theme, which are only known once `theme` is.
+    table: toml::Table,
+}
 
-                    // --- Layout with color emphasis and guaranteed baseline alignment ---
-                    let title_color = self.fg_color;
-                    let artist_color = Color32::from_gray(180);
+impl ConfigBuilder {
+    fn new() -> Self {
+        Self {
+            table: toml::Table::new(),
+        }
+    }
 
-                    ui.with_layout(egui::Layout::left_to_right(egui::Align::Center), |ui| {
-                        ui.add_space(5.0);
-                        ui.label(
-                            RichText::new(title.clone())
-                                .font(FontId::proportional(font_size))
-                                .color(title_color),
-                        );
-                        ui.label(
-                            RichText::new(format!("{}", artist))
-                                .font(FontId::proportional(font_size))
-                                .color(artist_color),
-                        );
-                    });
-                } else {
-                    let label = Label::new(
-                        RichText::new("No media playing")
-                            .font(FontId::proportional(16.0))
-                            .color(self.fg_color),
-                    );
-                    ui.with_layout(
-                        egui::Layout::left_to_right(egui::Align::Center),
-                        |ui| {
-                            ui.add_space(5.0);  // 5px left padding
-                            ui.add(label);
-                        },
-                    );
+    /// Merges in the config file. A missing file is fine unless it was asked for explicitly,
+    /// so the app can be configured with flags alone.
+    fn file(&mut self, path: Option<&str>, profile: Option<&str>) -> Result<(), String> {
+        let full_path = config_path(path);
+        let content = match fs::read_to_string(&full_path) {
+            Ok(content) => content,
+            Err(e) => {
+                if path.is_some() || profile.is_some() {
+                    eprintln!("Failed to read {}: {}. Using defaults.", full_path.display(), e);
                 }
-            });
-        // Request repaint to allow for updates from the D-Bus thread
-        ctx.request_repaint_after(Duration::from_millis(500));
+                return Ok(());
+            }
+        };
+        self.content(&content, profile)
+            .map_err(|e| format!("{}: {}", full_path.display(), e))
     }
-}
 
-fn extract_string_metadata(metadata: &HashMap<String, Value>, key: &str) -> Option<String> {
-    metadata.get(key).and_then(|value| {
-        OwnedValue::try_from(value)
-            .ok()
-            .and_then(|owned_value| TryInto::<String>::try_into(owned_value).ok())
-    })
+    /// Merges in a config file's `content`, with `profile` merged over its base settings.
+    fn content(&mut self, content: &str, profile: Option<&str>) -> Result<(), String> {
+        merge_tables(&mut self.table, Config::resolve(content, profile)?);
+        Ok(())
+    }
+
+    /// Merges in `DBUS_NOW_PLAYING_<OPTION>` variables, e.g. `DBUS_NOW_PLAYING_WINDOW_X=20`.
+    fn env(&mut self) {
+        self.vars(std::env::vars());
+    }
+
+    /// Merges in the `DBUS_NOW_PLAYING_<OPTION>` variables among `vars`.
+    fn vars(&mut self, vars: impl IntoIterator<Item = (String, String)>) {
+        for (name, value) in vars {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                self.table.insert(key.to_lowercase(), parse_override_value(&value));
+            }
+        }
+    }
+
+    fn overrides(&mut self, overrides: toml::Table) {
+        merge_tables(&mut self.table, overrides);
+    }
+
+    fn build(self) -> Result<Config, String> {
+        let mut table = toml::Table::try_from(Config::default()).unwrap_or_default();
+        // An unknown theme is reported by `validate`.
+        if let Some(preset) = self.table.get("theme").and_then(|v| v.as_str()).and_then(theme) {
+            merge_tables(&mut table, preset);
+        }
+        merge_tables(&mut table, self.table);
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| e.to_string())
+    }
 }
 
-fn extract_artist_metadata(metadata: &HashMap<String, Value>) -> Option<String> {
-    metadata.get("xesam:artist").and_then(|value| {
-        OwnedValue::try_from(value)
-            .ok()
-            .and_then(|owned_value| {
-                // Try to extract as Vec<String> first (most common case)
-                if let Ok(clone_value) = owned_value.try_clone() {
-                    if let Ok(artists_vec) = TryInto::<Vec<String>>::try_into(clone_value) {
-                        return artists_vec.first().cloned();
-                    }
-                }
-                // Fallback to single string
-                TryInto::<String>::try_into(owned_value).ok()
-            })
-    })
+/// Reads a value given on the command line or in the environment as TOML (so `20` is a number
+/// and `true` a boolean), falling back to a plain string for things like `#FF0000`.
+fn parse_override_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
 }
 
-fn discover_player(connection: &Connection) -> Result<Option<String>, zbus::Error> {
-    let proxy = Proxy::new(
-        connection,
-        "org.freedesktop.DBus",
-        "/org/freedesktop/DBus",
-        "org.freedesktop.DBus",
-    )?;
+/// The annotated example config, written out by `--init`.
+const EXAMPLE_CONFIG: &str = include_str!("../config.toml");
 
-    let all_names: Vec<String> = proxy.call_method("ListNames", &())?.body().deserialize()?;
+/// `--config`, else `config.toml` in the working directory if there is one, else the user's
+/// config directory.
+fn config_path(path: Option<&str>) -> PathBuf {
+    match path {
+        Some(path) => expand_path(path),
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => PathBuf::from(DEFAULT_CONFIG_PATH),
+        None => user_config_path(),
+    }
+}
 
-    let mpris_players: Vec<String> = all_names
-        .into_iter()
-        .filter(|name| name.starts_with("org.mpris.MediaPlayer2."))
-        .collect();
+/// `$XDG_CONFIG_HOME/dbus-now-playing/config.toml`, per the XDG base directory spec.
+fn user_config_path() -> PathBuf {
+    match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => expand_path("~/.config"),
+    }
+    .join("dbus-now-playing")
+    .join(DEFAULT_CONFIG_PATH)
+}
 
-    if mpris_players.is_empty() {
-        return Ok(None);
+/// Writes the example config to `--config` or the user's config directory. Returns the exit
+/// code.
+fn init_config(path: Option<&str>, force: bool) -> i32 {
+    let path = path.map_or_else(user_config_path, expand_path);
+    if path.exists() && !force {
+        eprintln!("{} already exists; pass --force to overwrite it.", path.display());
+        return 1;
     }
+    match write_atomic(&path, EXAMPLE_CONFIG) {
+        Ok(()) => {
+            println!("Wrote {}", path.display());
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+            1
+        }
+    }
+}
 
-    let mut playing_player = None;
-    let mut paused_player = None;
+/// Expands a leading `~` or `~user` and any `$VAR` / `${VAR}` references in a path from the
+/// config or command line. Unset variables expand to nothing; unknown users are left as written.
+fn expand_path(path: &str) -> PathBuf {
+    let mut expanded = String::new();
+    let mut rest = path;
+    if let Some(tilde) = rest.strip_prefix('~') {
+        let end = tilde.find('/').unwrap_or(tilde.len());
+        let home = match &tilde[..end] {
+            "" => Some(std::env::var("HOME").unwrap_or_default()),
+            user => home_dir_of(user),
+        };
+        if let Some(home) = home {
+            expanded.push_str(&home);
+            rest = &tilde[end..];
+        }
+    }
 
-    for player_name in &mpris_players {
-        if let Ok(player_proxy) = Proxy::new(
-            connection,
-            player_name.as_str(),
-            "/org/mpris/MediaPlayer2",
-            "org.mpris.MediaPlayer2.Player",
-        ) {
-            if let Ok(status) = player_proxy.get_property::<String>("PlaybackStatus") {
-                if status == "Playing" {
-                    playing_player = Some(player_name.clone());
-                    break;
-                } else if status == "Paused" && paused_player.is_none() {
-                    paused_player = Some(player_name.clone());
-                }
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, remainder) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", after),
             }
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            expanded.push_str(&std::env::var(name).unwrap_or_default());
         }
+        rest = remainder;
     }
+    expanded.push_str(rest);
+    PathBuf::from(expanded)
+}
 
-    Ok(playing_player.or(paused_player).or_else(|| mpris_players.first().cloned()))
+/// `user`'s home directory, from the password database.
+#[cfg(unix)]
+fn home_dir_of(user: &str) -> Option<String> {
+    let name = std::ffi::CString::new(user).ok()?;
+    let mut passwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut found = std::ptr::null_mut();
+    // Safety: every pointer is valid for the call. `found` is only set once `passwd` is filled
+    // in, with its strings pointing into `buf`, which outlives the read.
+    unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            passwd.as_mut_ptr(),
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        );
+        if found.is_null() {
+            return None;
+        }
+        Some(std::ffi::CStr::from_ptr((*found).pw_dir).to_string_lossy().into_owned())
+    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = Config::load();
-    let shared = Arc::new(Mutex::new(AppState { current: None }));
+#[cfg(not(unix))]
+fn home_dir_of(_user: &str) -> Option<String> {
+    None
+}
 
-    let shared_clone = Arc::clone(&shared);
-    let config_clone = config.clone();
-    thread::spawn(move || {
-        loop {
-            let connection = match Connection::session() {
-                Ok(c) => c,
-                Err(e) => {
-                    eprintln!("Failed to connect to D-Bus: {}. Retrying in 5s...", e);
-                    thread::sleep(Duration::from_secs(5));
-                    continue;
-                }
-            };
+/// Writes `content` to `path` via a temporary file and a rename, so a crash mid-write never
+/// leaves a truncated file behind.
+fn write_atomic(path: &Path, content: impl AsRef<[u8]>) -> std::io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)
+}
 
-            // --- Main Player Discovery Loop ---
-            loop {
-                let service_name_to_use = if let Some(name) = &config_clone.dbus_service {
-                    Some(name.clone())
-                } else {
-                    match discover_player(&connection) {
-                        Ok(Some(name)) => Some(name),
-                        _ => None,
-                    }
-                };
+/// Maps a `valign` setting onto the cross-axis alignment of the text row.
+fn parse_valign(name: &str) -> Option<egui::Align> {
+    match name {
+        "top" => Some(egui::Align::Min),
+        "center" => Some(egui::Align::Center),
+        "bottom" => Some(egui::Align::Max),
+        _ => None,
+    }
+}
 
-                if service_name_to_use.is_none() {
-                    let mut state = shared_clone.lock().unwrap();
-                    state.current = None;
-                    thread::sleep(Duration::from_secs(2));
-                    continue; // No player found, re-run discovery
-                }
-                
-                let service_name = service_name_to_use.unwrap();
-
-                let proxy_result = Proxy::new(
-                    &connection,
-                    service_name.as_str(),
-                    "/org/mpris/MediaPlayer2",
-                    "org.mpris.MediaPlayer2.Player",
-                );
+/// Accepts `#RRGGBB`, or `#RRGGBBAA` for translucency.
+fn is_valid_color(s: &str) -> bool {
+    let s = s.trim_start_matches('#');
+    matches!(s.len(), 6 | 8) && s.chars().all(|c| c.is_ascii_hexdigit())
+}
 
-                let proxy = match proxy_result {
-                    Ok(p) => p,
-                    Err(_) => {
-                        // Can't create proxy, player might have just closed.
-                        // Re-run discovery immediately.
-                        thread::sleep(Duration::from_millis(500));
-                        continue;
-                    }
-                };
+/// Recursively merges `overlay` into `base`, with `overlay` winning on conflicts.
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
 
-                // --- Track Info Polling Loop ---
-                loop {
-                    // First, check the playback status. If not "Playing", or if we get an error,
-                    // break out and re-run the discovery to find a new active player.
-                    match proxy.get_property::<String>("PlaybackStatus") {
-                        Ok(status) if status == "Playing" => {
-                            // All good, continue to get metadata.
-                        }
-                        _ => {
-                            // Player is paused, stopped, or has disconnected. Time to find a new one.
-                            let mut state = shared_clone.lock().unwrap();
-                            state.current = None;
-                            break;
-                        }
-                    }
-                    
-                    match proxy.get_property::<HashMap<String, Value>>("Metadata") {
-                        Ok(metadata) => {
-                            let title = extract_string_metadata(&metadata, "xesam:title")
-                                .unwrap_or_else(|| "Unknown Title".to_string());
-                            let artist = extract_artist_metadata(&metadata)
-                                .unwrap_or_else(|| "Unknown Artist".to_string());
-
-                            let mut state = shared_clone.lock().unwrap();
-                            if !title.is_empty() && !artist.is_empty() 
-                                && title != "Unknown Title" && artist != "Unknown Artist" {
-                                state.current = Some(NowPlaying { title, artist });
-                            } else {
-                                state.current = None;
-                            }
-                        }
-                        Err(_) => {
-                            // This error means the player probably closed unexpectedly.
-                            // Break out to re-run discovery.
-                            let mut state = shared_clone.lock().unwrap();
-                            state.current = None;
-                            break;
-                        }
-                    }
-                    thread::sleep(Duration::from_secs(1));
+/// Command-line options.
+#[derive(Default)]
+struct Args {
+    config: Option<String>,
+    profile: Option<String>,
+    player: Option<String>,
+    check_config: bool,
+    init: bool,
+    force: bool,
+    replace: bool,
+    kill: bool,
+    watch: bool,
+    follow: bool,
+    tui: bool,
+    /// Settings given as flags, layered over the config file.
+    overrides: toml::Table,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = Self::default();
+        let mut iter = std::env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--config" => {
+                    args.config = Some(iter.next().ok_or("--config requires a path")?);
+                }
+                "--profile" => {
+                    args.profile = Some(iter.next().ok_or("--profile requires a name")?);
+                }
+                "--player" => {
+                    args.player = Some(iter.next().ok_or("--player requires a name")?);
+                }
+                "--check-config" => args.check_config = true,
+                "--init" => args.init = true,
+                "--force" => args.force = true,
+                "--replace" => args.replace = true,
+                "--kill" => args.kill = true,
+                "--watch" => args.watch = true,
+                "--follow" => args.follow = true,
+                "--tui" => args.tui = true,
+                "--fg" => args.set("fg_color", iter.next().ok_or("--fg requires a color")?),
+                "--bg" => args.set("bg_color", iter.next().ok_or("--bg requires a color")?),
+                "--format" => {
+                    args.set("format", iter.next().ok_or("--format requires a template")?)
                 }
+                "--pos" => {
+                    let pos = iter.next().ok_or("--pos requires x,y")?;
+                    let (x, y) = pos
+                        .split_once(',')
+                        .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
+                        .ok_or_else(|| format!("--pos expects x,y, got '{}'", pos))?;
+                    args.overrides.insert("window_x".to_string(), toml::Value::Integer(x));
+                    args.overrides.insert("window_y".to_string(), toml::Value::Integer(y));
+                }
+                "--set" => {
+                    let setting = iter.next().ok_or("--set requires key=value")?;
+                    let (key, value) = setting
+                        .split_once('=')
+                        .ok_or_else(|| format!("--set expects key=value, got '{}'", setting))?;
+                    args.overrides
+                        .insert(key.trim().to_string(), parse_override_value(value.trim()));
+                }
+                other => return Err(format!("Unknown argument '{}'", other)),
             }
         }
-    });
-
-    let fg_color_parsed = Config::parse_color(&config.fg_color);
-    let bg_color_parsed = Config::parse_color(&config.bg_color);
-    let window_width = 400.0;
-    let window_height = 25.0;
-    let window_x = config.window_x.unwrap_or(0) as f32;
-    let window_y = config.window_y.unwrap_or(1000) as f32;
-    
-    //println!("Attempting to position window at: x={}, y={}", window_x, window_y);
-    
-    let native_options = NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_inner_size([window_width, window_height])
-            .with_position([window_x, window_y])
-            .with_decorations(false)
-            .with_always_on_top()
-            .with_resizable(false)
-            .with_transparent(true)
-            .with_taskbar(false)
-            .with_visible(true),
-        ..Default::default()
-    };
+        Ok(args)
+    }
+
+    fn set(&mut self, key: &str, value: String) {
+        self.overrides.insert(key.to_string(), toml::Value::String(value));
+    }
+}
+
+/// Pointer inputs that can be bound in `[click_actions]`.
+const CLICK_TRIGGERS: &[&str] = &["left", "right", "middle", "double", "scroll_up", "scroll_down"];
+
+/// Something the user can trigger from the widget.
+#[derive(Clone, Copy)]
+enum Action {
+    PlayPause,
+    Next,
+    Previous,
+    VolumeUp,
+    VolumeDown,
+    Raise,
+    /// Open the track's `xesam:url` (e.g. the video page) with `xdg-open`.
+    Open,
+    /// Show or hide the settings window.
+    Settings,
+    /// Stop or resume updating the display, e.g. to read or copy a long title.
+    Freeze,
+    /// Put the track text (or `copy_format`) on the clipboard.
+    Copy,
+    Quit,
+}
+
+impl Action {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "playpause" => Some(Self::PlayPause),
+            "next" => Some(Self::Next),
+            "previous" => Some(Self::Previous),
+            "volume_up" => Some(Self::VolumeUp),
+            "volume_down" => Some(Self::VolumeDown),
+            "raise" => Some(Self::Raise),
+            "open" => Some(Self::Open),
+            "settings" => Some(Self::Settings),
+            "freeze" => Some(Self::Freeze),
+            "copy" => Some(Self::Copy),
+            "quit" => Some(Self::Quit),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct NowPlaying {
+    title: String,
+    artist: String,
+    player: String,
+    /// Values for the [`METADATA_TOKENS`], keyed by token name.
+    fields: HashMap<&'static str, String>,
+    /// Only read when the format shows `{position_ms}` or the progress bar is on.
+    position: Option<Position>,
+    /// `xesam:asText`, only read with `show_lyrics`.
+    lyrics: Option<String>,
+    /// `mpris:artUrl`, only read with `art_output_path`.
+    art_url: Option<String>,
+    /// Whether the player is playing it, rather than it being paused or remembered from an
+    /// earlier run.
+    playing: bool,
+    /// `resume_hint`: where the track was left off, for a little while after coming back to it.
+    left_off_at: Option<i64>,
+}
+
+/// A `Position` reading, extrapolated at `Rate` to get the position at any later moment.
+#[derive(Clone)]
+struct Position {
+    micros: i64,
+    /// When the player reported `micros`.
+    at: Instant,
+    rate: f64,
+    /// `mpris:length`, which the extrapolated position never runs past.
+    length: Option<i64>,
+    /// `CanSeek`: whether the progress bar's knob can be dragged.
+    can_seek: bool,
+}
+
+impl Position {
+    fn now_ms(&self) -> i64 {
+        let elapsed = self.at.elapsed().as_secs_f64() * 1_000_000.0 * self.rate;
+        let mut micros = self.micros + elapsed as i64;
+        if let Some(length) = self.length.filter(|length| *length > 0) {
+            micros = micros.min(length);
+        }
+        micros.max(0) / 1000
+    }
+
+    /// How far through the track playback is, from 0 to 1. `None` without a known length.
+    fn fraction(&self) -> Option<f32> {
+        let length = self.length.filter(|length| *length > 0)?;
+        Some((self.now_ms() as f64 * 1000.0 / length as f64).clamp(0.0, 1.0) as f32)
+    }
+}
+
+impl NowPlaying {
+    /// Marks the track as no longer playing, holding its position where it is now.
+    fn pause(&mut self) {
+        self.playing = false;
+        if let Some(position) = &mut self.position {
+            position.micros = position.now_ms() * 1000;
+            position.at = Instant::now();
+            position.rate = 0.0;
+        }
+    }
+
+    fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "title" => Some(&self.title),
+            "artist" => Some(&self.artist),
+            "player" => Some(&self.player),
+            _ => self.fields.get(name).map(String::as_str),
+        }
+    }
+}
+
+/// Format tokens that map straight onto an `xesam:*` metadata key.
+const METADATA_TOKENS: &[(&str, &str)] = &[
+    ("album", "xesam:album"),
+    ("genre", "xesam:genre"),
+    ("composer", "xesam:composer"),
+    ("albumArtist", "xesam:albumArtist"),
+    ("discNumber", "xesam:discNumber"),
+    ("url", "xesam:url"),
+    ("contentCreated", "xesam:contentCreated"),
+    // No standard key exists; audiobook and podcast players that expose one pick their own.
+    ("chapter", "chapter"),
+    // From the Xesam ontology; few players fill them in, so these usually need a field_map entry.
+    ("bitrate", "xesam:audioBitrate"),
+    ("codec", "xesam:audioCodec"),
+];
+
+fn is_known_token(name: &str) -> bool {
+    matches!(
+        name,
+        "title"
+            | "artist"
+            | "player"
+            | "station"
+            | "rating"
+            | "position_ms"
+            | "time"
+            | "queue_pos"
+            | "queue_len"
+    )
+        || METADATA_TOKENS.iter().any(|(token, _)| *token == name)
+}
+
+/// Maps a playerctl template variable, such as `xesam:title` or `playerName`, onto our token.
+fn playerctl_token(variable: &str) -> Option<&'static str> {
+    match variable {
+        "title" | "xesam:title" => Some("title"),
+        "artist" | "xesam:artist" => Some("artist"),
+        "playerName" => Some("player"),
+        "album" => Some("album"),
+        _ => METADATA_TOKENS
+            .iter()
+            .find(|(_, key)| *key == variable)
+            .map(|(token, _)| *token),
+    }
+}
+
+/// Reproduces the original layout: title in `fg_color`, artist in `artist_color`.
+const DEFAULT_FORMAT: &str = "{title}{artist}";
+/// The muted gray the artist is drawn in unless `artist_color` says otherwise.
+const DEFAULT_ARTIST_COLOR: Color32 = Color32::from_gray(180);
+/// The progress bar's color while paused, unless `paused_color` says otherwise.
+const DEFAULT_PAUSED_COLOR: Color32 = Color32::from_gray(128);
+/// Color of `error_text` unless `error_color` says otherwise.
+const DEFAULT_ERROR_COLOR: Color32 = Color32::from_rgb(255, 110, 110);
+/// Height of the `show_lyrics` line, added below the track.
+const LYRICS_HEIGHT: f32 = 16.0;
+/// Radius of the progress bar's seek knob.
+const KNOB_RADIUS: f32 = 5.0;
+/// Width of the `eq_icon` bars, gaps included.
+const EQ_ICON_WIDTH: f32 = 13.0;
+/// How visible the window stays while `fade_on_hover` has faded it out.
+const HOVER_OPACITY: f32 = 0.15;
+/// How long, in seconds, the `copy` action's confirmation stays up.
+const COPIED_DURATION: f64 = 1.0;
+
+enum FormatPart {
+    Literal(String),
+    Token { name: String, color: Option<Color32> },
+}
+
+/// A run of display text with an optional color override.
+struct Segment {
+    text: String,
+    color: Option<Color32>,
+    /// Whether this is the `{time}` readout, which a click switches between elapsed and remaining.
+    time: bool,
+}
+
+/// Case mapping applied to the displayed track text.
+#[derive(Clone, Copy)]
+enum TextTransform {
+    None,
+    Upper,
+    Lower,
+    Title,
+}
+
+impl TextTransform {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "upper" => Some(Self::Upper),
+            "lower" => Some(Self::Lower),
+            "title" => Some(Self::Title),
+            _ => None,
+        }
+    }
+
+    fn apply(self, text: &str) -> String {
+        match self {
+            Self::None => text.to_string(),
+            Self::Upper => text.to_uppercase(),
+            Self::Lower => text.to_lowercase(),
+            Self::Title => {
+                // Uppercase the first letter of every whitespace-separated word.
+                let mut result = String::with_capacity(text.len());
+                let mut at_word_start = true;
+                for c in text.chars() {
+                    if c.is_whitespace() {
+                        at_word_start = true;
+                        result.push(c);
+                    } else if at_word_start && c.is_alphabetic() {
+                        at_word_start = false;
+                        result.extend(c.to_uppercase());
+                    } else {
+                        if c.is_alphanumeric() {
+                            at_word_start = false;
+                        }
+                        result.extend(c.to_lowercase());
+                    }
+                }
+                result
+            }
+        }
+    }
+}
+
+/// A parsed format string such as `"{title} - {artist:#888888}"`.
+struct Template {
+    parts: Vec<FormatPart>,
+    /// Problems found while parsing; the offending tokens render uncolored or empty.
+    errors: Vec<String>,
+    /// Colors for tokens that don't specify their own, keyed by token name.
+    default_colors: HashMap<String, Color32>,
+    /// Longest a token's text may get before it is cut short, in characters.
+    max_chars: HashMap<String, usize>,
+    /// Appended to text cut short by `max_chars`, and counted towards the limit.
+    ellipsis: String,
+    /// Show `{time}` as the time remaining rather than elapsed.
+    count_down: bool,
+}
+
+impl Template {
+    fn parse(format: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut errors = Vec::new();
+        let mut rest = format;
+        while let Some(start) = rest.find('{') {
+            // playerctl-style `{{xesam:title}}`.
+            if let Some(after) = rest[start..].strip_prefix("{{") {
+                let Some(len) = after.find("}}") else {
+                    break;
+                };
+                if start > 0 {
+                    parts.push(FormatPart::Literal(rest[..start].to_string()));
+                }
+                let inner = after[..len].trim();
+                let name = playerctl_token(inner).unwrap_or_else(|| {
+                    errors.push(format!("format uses unknown variable '{{{{{}}}}}'", inner));
+                    inner
+                });
+                parts.push(FormatPart::Token {
+                    name: name.to_string(),
+                    color: None,
+                });
+                rest = &after[len + 2..];
+                continue;
+            }
+            let Some(len) = rest[start..].find('}') else {
+                break;
+            };
+            if start > 0 {
+                parts.push(FormatPart::Literal(rest[..start].to_string()));
+            }
+            let inner = &rest[start + 1..start + len];
+            let (name, color) = match inner.split_once(':') {
+                Some((name, color)) if is_valid_color(color) => {
+                    (name, Some(Config::parse_color(color)))
+                }
+                Some((name, color)) => {
+                    errors.push(format!("format token '{{{}}}' has invalid color '{}'", name, color));
+                    (name, None)
+                }
+                None => (inner, None),
+            };
+            if !is_known_token(name.trim()) {
+                errors.push(format!("format uses unknown token '{{{}}}'", name.trim()));
+            }
+            parts.push(FormatPart::Token {
+                name: name.trim().to_string(),
+                color,
+            });
+            rest = &rest[start + len + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(FormatPart::Literal(rest.to_string()));
+        }
+        Self {
+            parts,
+            errors,
+            default_colors: HashMap::new(),
+            max_chars: HashMap::new(),
+            ellipsis: "…".to_string(),
+            count_down: false,
+        }
+    }
+
+    fn uses_token(&self, name: &str) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, FormatPart::Token { name: token, .. } if token == name))
+    }
+
+    /// Gives every `{name}` token without its own color `color`.
+    fn set_default_color(&mut self, name: &str, color: Color32) {
+        self.default_colors.insert(name.to_string(), color);
+    }
+
+    fn render(&self, current: &NowPlaying) -> Vec<Segment> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                FormatPart::Literal(text) => Some(Segment {
+                    text: text.clone(),
+                    color: None,
+                    time: false,
+                }),
+                FormatPart::Token { name, color } => {
+                    let text = match name.as_str() {
+                        "position_ms" => current.position.as_ref().map(|p| p.now_ms().to_string()),
+                        "time" => current.position.as_ref().map(|p| self.time(p)),
+                        _ => current.field(name).map(str::to_string),
+                    };
+                    let text = match self.max_chars.get(name) {
+                        Some(max) => text.map(|text| truncate(&text, *max, &self.ellipsis)),
+                        None => text,
+                    };
+                    text.map(|text| Segment {
+                        text,
+                        color: color.or_else(|| self.default_colors.get(name).copied()),
+                        time: name == "time",
+                    })
+                }
+            })
+            .filter(|segment| !segment.text.is_empty())
+            .collect()
+    }
+
+    /// The `{time}` readout: elapsed, or remaining with `count_down` when the length is known.
+    fn time(&self, position: &Position) -> String {
+        let elapsed = position.now_ms();
+        match position.length.filter(|length| *length > 0 && self.count_down) {
+            Some(length) => format!("-{}", clock_time((length / 1000 - elapsed).max(0))),
+            None => clock_time(elapsed),
+        }
+    }
+}
+
+/// Puts the `prefix` and `suffix` labels around `segments`, where they are set.
+fn add_affixes(segments: &mut Vec<Segment>, prefix: &str, suffix: &str) {
+    let label = |text: &str| Segment {
+        text: text.to_string(),
+        color: None,
+        time: false,
+    };
+    if !prefix.is_empty() {
+        segments.insert(0, label(prefix));
+    }
+    if !suffix.is_empty() {
+        segments.push(label(suffix));
+    }
+}
+
+/// `ms` as `m:ss`, or `h:mm:ss` from an hour up.
+fn clock_time(ms: i64) -> String {
+    let seconds = ms / 1000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// The line of `lyrics` to show at `position`. LRC-style `[mm:ss.xx]` timestamps are followed
+/// when the lyrics have them; otherwise the lines are spread evenly over the track, or the first
+/// one is shown when its length isn't known.
+fn lyric_line<'a>(lyrics: &'a str, position: Option<&Position>) -> Option<&'a str> {
+    let lines: Vec<&str> = lyrics.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+    let mut timed: Vec<(i64, &str)> = lines
+        .iter()
+        .filter_map(|line| lrc_line(line))
+        .flat_map(|(stamps, text)| stamps.into_iter().map(move |at| (at, text)))
+        .collect();
+    timed.sort_by_key(|(at, _)| *at);
+    if let (false, Some(position)) = (timed.is_empty(), position) {
+        let now = position.now_ms();
+        return timed
+            .iter()
+            .take_while(|(at, _)| *at <= now)
+            .last()
+            .or(timed.first())
+            .map(|(_, text)| *text);
+    }
+    let index = position
+        .and_then(Position::fraction)
+        .map_or(0, |fraction| (fraction * lines.len() as f32) as usize);
+    let line = *lines.get(index.min(lines.len().saturating_sub(1)))?;
+    Some(lrc_line(line).map_or(line, |(_, text)| text))
+}
+
+/// Splits `[mm:ss.xx] text` into its timestamps in milliseconds and its text. A line sung more
+/// than once may start with several timestamps, as in `[00:12.00][01:30.00] text`.
+fn lrc_line(line: &str) -> Option<(Vec<i64>, &str)> {
+    let mut stamps = Vec::new();
+    let mut rest = line;
+    while let Some((stamp, text)) = rest.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        let Some(ms) = lrc_time(stamp) else {
+            break;
+        };
+        stamps.push(ms);
+        rest = text.trim_start();
+    }
+    (!stamps.is_empty()).then(|| (stamps, rest.trim()))
+}
+
+/// Reads an LRC `mm:ss.xx` timestamp as milliseconds.
+fn lrc_time(stamp: &str) -> Option<i64> {
+    let (minutes, seconds) = stamp.split_once(':')?;
+    let minutes: i64 = minutes.parse().ok()?;
+    let seconds: f64 = seconds.parse().ok()?;
+    Some(minutes * 60_000 + (seconds * 1000.0) as i64)
+}
+
+/// Cuts `text` down to `max` characters, ending in `ellipsis` when anything was dropped.
+fn truncate(text: &str, max: usize, ellipsis: &str) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let kept = max.saturating_sub(ellipsis.chars().count());
+    if kept == 0 {
+        // No room for any text before the ellipsis; the text itself says more.
+        return text.chars().take(max).collect();
+    }
+    let cut: String = text.chars().take(kept).collect();
+    cut.trim_end().to_string() + ellipsis
+}
+
+struct AppState {
+    current: Option<NowPlaying>,
+    /// Set while the session bus can't be reached, so that isn't mistaken for nothing playing.
+    bus_error: Option<String>,
+}
+
+/// Where `progress_position` draws the progress bar.
+#[derive(Clone, Copy)]
+enum ProgressPosition {
+    Top,
+    Bottom,
+    /// Fills the window from the left behind the text.
+    Background,
+}
+
+impl ProgressPosition {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "top" => Some(Self::Top),
+            "bottom" => Some(Self::Bottom),
+            "background" => Some(Self::Background),
+            _ => None,
+        }
+    }
+}
+
+/// The playback progress bar, shown while the player reports a position and track length.
+struct ProgressBar {
+    position: ProgressPosition,
+    /// Thickness of the `top`/`bottom` bar; the background bar fills the window height.
+    height: f32,
+    color: Color32,
+    /// Used instead of `color` while the track isn't playing.
+    paused_color: Color32,
+}
+
+impl ProgressBar {
+    /// The whole bar within the window's `rect`, filled or not.
+    fn rect(&self, rect: egui::Rect) -> egui::Rect {
+        let mut bar = rect;
+        match self.position {
+            ProgressPosition::Top => bar.set_height(self.height),
+            ProgressPosition::Bottom => bar.set_top(rect.bottom() - self.height),
+            ProgressPosition::Background => {}
+        }
+        bar
+    }
+
+    /// Where the knob can be grabbed: the bar, widened so a thin one is still easy to hit.
+    fn grab_rect(&self, rect: egui::Rect) -> egui::Rect {
+        let bar = self.rect(rect);
+        let margin = (KNOB_RADIUS * 2.0 - bar.height()).max(0.0) / 2.0;
+        bar.expand2(egui::vec2(0.0, margin)).intersect(rect)
+    }
+
+    /// Paints the bar filled to `fraction`, with the seek knob at its end if `knob` is set.
+    fn paint(&self, ui: &egui::Ui, rect: egui::Rect, fraction: f32, knob: bool, playing: bool) {
+        let color = if playing { self.color } else { self.paused_color };
+        let mut bar = self.rect(rect);
+        bar.set_width(rect.width() * fraction);
+        ui.painter().rect_filled(bar, 0.0, color);
+        if knob {
+            // Keep the whole knob inside the window, even on a bar along the edge.
+            let y = bar
+                .center()
+                .y
+                .clamp(rect.top() + KNOB_RADIUS, rect.bottom() - KNOB_RADIUS);
+            ui.painter()
+                .circle_filled(egui::pos2(bar.right(), y), KNOB_RADIUS, color.to_opaque());
+        }
+    }
+}
+
+/// Where copies of a label go to draw a `width` pixel outline around it: rings of offsets, one
+/// per pixel of width, spaced closely enough to leave no gaps.
+fn outline_offsets(width: f32) -> Vec<egui::Vec2> {
+    let rings = width.ceil().max(1.0) as usize;
+    (1..=rings)
+        .flat_map(|ring| {
+            let radius = (ring as f32).min(width);
+            let steps = ((std::f32::consts::TAU * radius).ceil() as usize).max(8);
+            (0..steps).map(move |step| {
+                let angle = std::f32::consts::TAU * step as f32 / steps as f32;
+                egui::vec2(angle.cos(), angle.sin()) * radius
+            })
+        })
+        .collect()
+}
+
+/// A row of the track: the `format`, or one of the `lines`.
+struct Line {
+    template: Template,
+    /// How far across its free space the row is pushed: 0 is left, 1 right. `None` leaves it
+    /// where its text starts.
+    align: Option<f32>,
+    /// Color of the row's plain text; `fg_color` if unset.
+    color: Option<Color32>,
+}
+
+/// `progress_tint`: the background filled up to the playback position, shading from `start`
+/// at the left edge to `end` at the right, so the color deepens as the track goes on.
+struct ProgressTint {
+    start: Color32,
+    end: Color32,
+}
+
+impl ProgressTint {
+    fn paint(&self, ui: &egui::Ui, rect: egui::Rect, fraction: f32) {
+        if fraction <= 0.0 {
+            return;
+        }
+        let mut fill = rect;
+        fill.set_width(rect.width() * fraction);
+        let edge = self.start.lerp_to_gamma(self.end, fraction);
+        let mut mesh = egui::Mesh::default();
+        mesh.colored_vertex(fill.left_top(), self.start);
+        mesh.colored_vertex(fill.right_top(), edge);
+        mesh.colored_vertex(fill.right_bottom(), edge);
+        mesh.colored_vertex(fill.left_bottom(), self.start);
+        mesh.add_triangle(0, 1, 2);
+        mesh.add_triangle(0, 2, 3);
+        ui.painter().add(mesh);
+    }
+}
+
+/// `bg_image`, uploaded once and painted over `bg_color` every frame.
+struct BgImage {
+    texture: egui::TextureHandle,
+    /// Repeat at the image's own size instead of stretching it over the window.
+    tile: bool,
+    tint: Color32,
+}
+
+impl BgImage {
+    fn paint(&self, ui: &egui::Ui, rect: egui::Rect, corner_radius: f32) {
+        let uv = if self.tile {
+            let size = self.texture.size_vec2();
+            egui::Rect::from_min_size(egui::Pos2::ZERO, rect.size() / size)
+        } else {
+            egui::Rect::from_min_max(egui::Pos2::ZERO, egui::pos2(1.0, 1.0))
+        };
+        egui::Image::from_texture(&self.texture)
+            .uv(uv)
+            .tint(self.tint)
+            .corner_radius(corner_radius)
+            .paint_at(ui, rect);
+    }
+}
+
+/// Decodes the image at `path`, warning and returning `None` when it can't be used.
+fn load_bg_image(path: &Path) -> Option<egui::ColorImage> {
+    let image = match image::open(path) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => {
+            eprintln!("Failed to load bg_image {}: {}. Using bg_color.", path.display(), e);
+            return None;
+        }
+    };
+    let size = [image.width() as usize, image.height() as usize];
+    Some(egui::ColorImage::from_rgba_unmultiplied(size, image.as_raw()))
+}
+
+struct NowPlayingApp {
+    shared: Arc<Mutex<AppState>>,
+    fg_color: Color32,
+    bg_color: Color32,
+    corner_radius: f32,
+    /// Drawn along the inside edge of the window; `Stroke::NONE` unless configured.
+    border: egui::Stroke,
+    /// Drawn around the glyphs of every label; `Stroke::NONE` unless configured.
+    text_outline: egui::Stroke,
+    /// Set when the window isn't transparent, so nothing behind the panel should show through.
+    opaque: bool,
+    idle_text: String,
+    /// Fixed labels around the track, in `fg_color`.
+    prefix: String,
+    suffix: String,
+    /// Shown instead of `idle_text` while the session bus is unreachable.
+    error_text: String,
+    error_color: Color32,
+    /// The rows the track is shown in: each of `lines`, or just `format`.
+    lines: Vec<Line>,
+    text_transform: TextTransform,
+    font_size_range: (f32, f32),
+    /// Disables dynamic sizing when set.
+    fixed_font_size: Option<f32>,
+    bg_image: Option<BgImage>,
+    progress: Option<ProgressBar>,
+    progress_tint: Option<ProgressTint>,
+    /// Lower bound on the time between repaints, from `max_fps`.
+    min_frame_time: Duration,
+    click_actions: HashMap<String, Action>,
+    worker: Sender<WorkerEvent>,
+    /// Forced text direction; `None` picks it from the text being shown.
+    rtl: Option<bool>,
+    /// Where the text row sits vertically in the window.
+    valign: egui::Align,
+    /// Set by the watcher while a fullscreen window is active, if `hide_over_fullscreen` is on.
+    fullscreen_active: Option<Arc<AtomicBool>>,
+    lowered: bool,
+    /// Set by the `freeze` action; the worker leaves the track alone while it is.
+    frozen: Arc<AtomicBool>,
+    /// Fade the window out while the pointer is over it.
+    fade_on_hover: bool,
+    /// Show a line of the track's lyrics under it.
+    show_lyrics: bool,
+    eq_icon: bool,
+    /// Seconds the `eq_icon` bars have been bouncing for; only advances while playing.
+    eq_clock: f64,
+    /// Parsed `copy_format`; `None` copies the displayed text.
+    copy_template: Option<Template>,
+    /// `ctx` time of the last `copy`, to show a confirmation for a moment.
+    copied_at: Option<f64>,
+    /// Length of the fade between the idle text and a track; zero disables it.
+    fade: Duration,
+    /// Whether the last frame showed a track, to spot the idle/playing boundary.
+    showing_track: Option<bool>,
+    /// `ctx` time at which the current fade started.
+    fade_started: Option<f64>,
+    /// Ask the worker for a fresh poll when the window reappears or gains focus.
+    refresh_on_show: bool,
+    /// Whether the window was visible / focused last frame; `None` before the first frame.
+    was_visible: Option<bool>,
+    was_focused: bool,
+    /// The loaded config and where it came from, for the settings window.
+    config: Config,
+    config_path: PathBuf,
+    /// Top-left corner of the `monitor` the window position is relative to.
+    monitor_origin: (i32, i32),
+    artist_color: Color32,
+    settings: Option<settings::Settings>,
+    #[cfg(feature = "audio-meter")]
+    audio_meter: Option<audio_meter::AudioMeter>,
+}
+
+impl NowPlayingApp {
+    /// How wide `segments` come out at `font_size`.
+    fn segments_width(&self, ctx: &Context, segments: &[Segment], font_size: f32) -> f32 {
+        ctx.fonts(|fonts| {
+            // Measure each segment separately, as they are laid out as separate labels
+            segments
+                .iter()
+                .map(|segment| {
+                    fonts
+                        .layout_no_wrap(
+                            segment.text.clone(),
+                            FontId::proportional(font_size),
+                            self.fg_color,
+                        )
+                        .size()
+                        .x
+                })
+                .sum::<f32>()
+        }) + 2.0 * self.text_outline.width
+    }
+
+    /// Whether any row shows the `{name}` token.
+    fn uses_token(&self, name: &str) -> bool {
+        self.lines.iter().any(|line| line.template.uses_token(name))
+    }
+
+    /// Whether `text` is laid out right to left.
+    fn is_rtl(&self, text: &str) -> bool {
+        self.rtl.unwrap_or_else(|| is_rtl_text(text))
+    }
+
+    /// Picks the largest font size in range at which `segments` fit in `available_width`,
+    /// or the fixed size when one is configured.
+    fn font_size_for(&self, ctx: &Context, segments: &[Segment], available_width: f32) -> f32 {
+        if let Some(size) = self.fixed_font_size {
+            return size;
+        }
+
+        // --- Dynamic font sizing ---
+        let (min_font_size, max_font_size) = self.font_size_range;
+        let padding = 10.0;
+        let target_width = available_width - padding;
+
+        let mut font_size = max_font_size;
+        loop {
+            let total_width = self.segments_width(ctx, segments, font_size);
+
+            if total_width <= target_width || font_size <= min_font_size {
+                break;
+            }
+            font_size -= 1.0;
+        }
+        font_size
+    }
+
+    /// A single-row layout running in the direction of `text`. The leading padding added
+    /// first then ends up on the correct side.
+    fn line_layout(&self, text: &str) -> egui::Layout {
+        if self.is_rtl(text) {
+            egui::Layout::right_to_left(self.valign)
+        } else {
+            egui::Layout::left_to_right(self.valign)
+        }
+    }
+
+    /// Draws one row of the track into `rect`. The first row also carries the `eq_icon` and the
+    /// audio meter. Returns where the `{time}` token went, if the row shows it.
+    fn track_row(
+        &self,
+        ctx: &Context,
+        ui: &mut egui::Ui,
+        rect: egui::Rect,
+        line: &Line,
+        segments: Vec<Segment>,
+        first: bool,
+    ) -> Option<egui::Rect> {
+        let eq_icon = self.eq_icon && first;
+        let eq_width = if eq_icon { EQ_ICON_WIDTH + 4.0 } else { 0.0 };
+        let font_size = self.font_size_for(ctx, &segments, rect.width() - eq_width);
+
+        // --- Layout with color emphasis and guaranteed baseline alignment ---
+        let text: String = segments.iter().map(|s| s.text.as_str()).collect();
+        let rtl = self.is_rtl(&text);
+        // An aligned row is pushed across whatever width it leaves free, counted from the side
+        // the text starts on.
+        let offset = line.align.map_or(0.0, |align| {
+            let used = self.segments_width(ctx, &segments, font_size) + eq_width + 10.0;
+            let free = (rect.width() - used).max(0.0);
+            free * if rtl { 1.0 - align } else { align }
+        });
+        let mut time_rect = None;
+        let builder = egui::UiBuilder::new().max_rect(rect).layout(self.line_layout(&text));
+        ui.scope_builder(builder, |ui| {
+            ui.add_space(5.0 + offset);
+            for segment in segments {
+                let color = segment.color.or(line.color).unwrap_or(self.fg_color);
+                let label = self.label(ui, segment.text, FontId::proportional(font_size), color);
+                if segment.time {
+                    time_rect = Some(label.rect);
+                }
+            }
+            if eq_icon {
+                let (rect, _) = ui.allocate_exact_size(
+                    egui::vec2(EQ_ICON_WIDTH, font_size * 0.8),
+                    egui::Sense::hover(),
+                );
+                paint_eq_icon(ui, rect, self.eq_clock, self.fg_color);
+            }
+            #[cfg(feature = "audio-meter")]
+            if let Some(meter) = self.audio_meter.as_ref().filter(|_| first) {
+                let height = ui.available_height();
+                let (rect, _) =
+                    ui.allocate_exact_size(egui::vec2(4.0, height), egui::Sense::hover());
+                let mut bar = rect;
+                bar.set_top(rect.bottom() - height * meter.level());
+                ui.painter().rect_filled(bar, 0.0, self.fg_color);
+            }
+        });
+        time_rect
+    }
+
+    /// Shows a message such as the idle text in place of the track.
+    fn status_line(&self, ui: &mut egui::Ui, text: &str, color: Color32) {
+        ui.with_layout(
+            self.line_layout(text),
+            |ui| {
+                ui.add_space(5.0);  // 5px leading padding
+                self.label(ui, text.to_string(), FontId::proportional(16.0), color);
+            },
+        );
+    }
+
+    /// Adds a label, with the `text_outline` painted behind its glyphs if one is set.
+    fn label(
+        &self,
+        ui: &mut egui::Ui,
+        text: String,
+        font: FontId,
+        color: Color32,
+    ) -> egui::Response {
+        let outline = self.text_outline;
+        if outline.is_empty() {
+            return ui.add(Label::new(RichText::new(text).font(font).color(color)));
+        }
+        // The outline is the label's own galley, recolored, so it lines up with the glyphs
+        // wherever the layout puts them.
+        let label = RichText::new(text).font(font).color(color);
+        let (pos, galley, response) = Label::new(label).layout_in_ui(ui);
+        let copies = outline_offsets(outline.width).into_iter().map(|offset| {
+            egui::Shape::galley_with_override_text_color(
+                pos + offset,
+                galley.clone(),
+                outline.color,
+            )
+        });
+        ui.painter().extend(copies);
+        ui.painter().galley(pos, galley, color);
+        response
+    }
+
+    /// What the `copy` action copies: `copy_format` if set, otherwise the text as displayed.
+    fn copy_text(&self) -> Option<String> {
+        let shared = self.shared.lock().unwrap();
+        let current = shared.current.as_ref()?;
+        let text: String = match &self.copy_template {
+            Some(template) => template.render(current).into_iter().map(|s| s.text).collect(),
+            None => self
+                .lines
+                .iter()
+                .map(|line| {
+                    let segments = line.template.render(current);
+                    segments.iter().map(|s| self.text_transform.apply(&s.text)).collect()
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+        };
+        Some(text)
+    }
+
+    /// Dispatches the action bound to whichever pointer input happened on `response`.
+    fn handle_clicks(&mut self, ctx: &Context, response: &egui::Response) {
+        if self.click_actions.is_empty() {
+            return;
+        }
+        let mut triggers = Vec::new();
+        if response.double_clicked() {
+            triggers.push("double");
+        } else if response.clicked() {
+            triggers.push("left");
+        }
+        if response.secondary_clicked() {
+            triggers.push("right");
+        }
+        if response.middle_clicked() {
+            triggers.push("middle");
+        }
+        if response.hovered() {
+            let scroll = ctx.input(|i| i.raw_scroll_delta.y);
+            if scroll > 0.0 {
+                triggers.push("scroll_up");
+            } else if scroll < 0.0 {
+                triggers.push("scroll_down");
+            }
+        }
+
+        let actions: Vec<Action> = triggers
+            .iter()
+            .filter_map(|t| self.click_actions.get(*t).copied())
+            .collect();
+        for action in &actions {
+            match action {
+                Action::Quit => ctx.send_viewport_cmd(egui::ViewportCommand::Close),
+                Action::Copy => {
+                    if let Some(text) = self.copy_text() {
+                        ctx.copy_text(text);
+                        self.copied_at = Some(ctx.input(|i| i.time));
+                    }
+                }
+                Action::Freeze => {
+                    let was_frozen = self.frozen.fetch_xor(true, Ordering::Relaxed);
+                    // Catch up on whatever changed in the meantime.
+                    if was_frozen {
+                        let _ = self.worker.send(WorkerEvent::Refresh);
+                    }
+                }
+                Action::Settings => {
+                    self.settings = match self.settings {
+                        Some(_) => None,
+                        None => Some(settings::Settings::new(
+                            self.config_path.clone(),
+                            &self.config,
+                            self.artist_color,
+                            &self.worker,
+                        )),
+                    };
+                }
+                _ => {
+                    let _ = self.worker.send(WorkerEvent::Command(*action));
+                }
+            }
+        }
+    }
+}
+
+impl NowPlayingApp {
+    /// Applies settings just saved from the settings window.
+    fn apply_settings(&mut self, ctx: &Context, saved: settings::Saved) {
+        self.fg_color = saved.fg_color;
+        self.bg_color = saved.bg_color;
+        self.artist_color = saved.artist_color;
+        for line in &mut self.lines {
+            line.template.set_default_color("artist", saved.artist_color);
+        }
+        let (x, y) = saved.position;
+        let (origin_x, origin_y) = self.monitor_origin;
+        ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(egui::pos2(
+            (origin_x + x) as f32,
+            (origin_y + y) as f32,
+        )));
+        if self.config.dbus_service != saved.player {
+            let _ = self.worker.send(WorkerEvent::SetPlayer(saved.player.clone()));
+        }
+
+        self.config.fg_color = settings::color_hex(saved.fg_color);
+        self.config.bg_color = settings::color_hex(saved.bg_color);
+        self.config.artist_color = Some(settings::color_hex(saved.artist_color));
+        self.config.window_x = Some(x);
+        self.config.window_y = Some(y);
+        self.config.dbus_service = saved.player;
+    }
+}
+
+impl App for NowPlayingApp {
+    fn clear_color(&self, _visuals: &egui::Visuals) -> [f32; 4] {
+        if self.opaque {
+            return self.bg_color.to_normalized_gamma_f32();
+        }
+        // The panel paints the background; keep the area outside rounded corners see-through.
+        [0.0; 4]
+    }
+
+    fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
+        if let Some(fullscreen_active) = &self.fullscreen_active {
+            // Drop below a fullscreen window instead of covering it, and come back on top after.
+            let lower = fullscreen_active.load(Ordering::Relaxed);
+            if lower != self.lowered {
+                self.lowered = lower;
+                let level = if lower {
+                    egui::WindowLevel::Normal
+                } else {
+                    egui::WindowLevel::AlwaysOnTop
+                };
+                ctx.send_viewport_cmd(egui::ViewportCommand::WindowLevel(level));
+            }
+        }
+
+        if self.refresh_on_show {
+            // Don't show stale data on reappearance: re-poll instead of waiting for the next
+            // poll interval.
+            let (minimized, focused) = ctx.input(|i| {
+                let viewport = i.viewport();
+                (viewport.minimized.unwrap_or(false), viewport.focused.unwrap_or(false))
+            });
+            let visible = !minimized && !self.lowered;
+            let reappeared = self.was_visible == Some(false) && visible;
+            if reappeared || (focused && !self.was_focused) {
+                let _ = self.worker.send(WorkerEvent::Refresh);
+            }
+            self.was_visible = Some(visible);
+            self.was_focused = focused;
+        }
+
+        // Drawn from a copy, so the worker is never kept waiting on the lock for a whole frame.
+        let (current, bus_down) = {
+            let shared = self.shared.lock().unwrap();
+            (shared.current.clone(), shared.bus_error.is_some())
+        };
+        // Fade only when a track appears or goes away, not on track changes or repaints.
+        let has_track = current.is_some();
+        let now = ctx.input(|i| i.time);
+        // The bars stand still while the shown track isn't actually playing.
+        let eq_bouncing = self.eq_icon && current.as_ref().is_some_and(|c| c.playing);
+        if eq_bouncing {
+            self.eq_clock += f64::from(ctx.input(|i| i.stable_dt).min(0.1));
+        }
+        if !self.fade.is_zero() && self.showing_track.is_some_and(|showing| showing != has_track) {
+            self.fade_started = Some(now);
+        }
+        self.showing_track = Some(has_track);
+        let fade_progress = self
+            .fade_started
+            .map(|start| ((now - start) / self.fade.as_secs_f64()) as f32)
+            .filter(|progress| *progress < 1.0);
+
+        let copied = self.copied_at.is_some_and(|at| now - at < COPIED_DURATION);
+        // Get out of the way while the pointer is over the window.
+        let opacity = if self.fade_on_hover {
+            let hovered = ctx.input(|i| i.pointer.hover_pos().is_some());
+            let t = ctx.animate_bool_with_time(egui::Id::new("fade_on_hover"), hovered, 0.25);
+            1.0 - t * (1.0 - HOVER_OPACITY)
+        } else {
+            1.0
+        };
+        // A faint border shows that the display is frozen, replacing the configured one.
+        let stroke = if self.frozen.load(Ordering::Relaxed) {
+            egui::Stroke::new(
+                self.border.width.max(1.0),
+                self.fg_color.gamma_multiply(0.5 * opacity),
+            )
+        } else {
+            egui::Stroke::new(self.border.width, self.border.color.gamma_multiply(opacity))
+        };
+        CentralPanel::default()
+            .frame(
+                egui::Frame::default()
+                    .fill(self.bg_color.gamma_multiply(opacity))
+                    .stroke(stroke)
+                    .corner_radius(self.corner_radius),
+            )
+            .show(ctx, |ui| {
+                let full_rect = ui.max_rect();
+                let mut time_rect = None;
+                ui.set_opacity(opacity);
+                if let Some(bg_image) = &self.bg_image {
+                    bg_image.paint(ui, ui.max_rect(), self.corner_radius);
+                }
+                // Idle -> playing fades the idle text out, then the track in. Playing -> idle
+                // fades the idle text in, as the track is already gone.
+                let mut idle_fading_out = false;
+                if let Some(progress) = fade_progress {
+                    if has_track && progress < 0.5 {
+                        idle_fading_out = true;
+                        ui.multiply_opacity(1.0 - 2.0 * progress);
+                    } else if has_track {
+                        ui.multiply_opacity(2.0 * progress - 1.0);
+                    } else {
+                        ui.multiply_opacity(progress);
+                    }
+                }
+                if copied {
+                    self.status_line(ui, "Copied!", self.fg_color);
+                } else if let Some(current) = current.as_ref().filter(|_| !idle_fading_out) {
+                    let fraction = current.position.as_ref().and_then(Position::fraction);
+                    if let (Some(tint), Some(fraction)) = (&self.progress_tint, fraction) {
+                        tint.paint(ui, full_rect, fraction);
+                    }
+                    if let (Some(progress), Some(mut fraction)) = (&self.progress, fraction) {
+                        // Players that can seek get a knob, shown on hover and dragged to seek.
+                        // Registered before the click actions so clicks still reach those.
+                        let mut knob = false;
+                        if current.position.as_ref().is_some_and(|p| p.can_seek) {
+                            let bar = progress.grab_rect(full_rect);
+                            let response =
+                                ui.interact(bar, ui.id().with("seek"), egui::Sense::drag());
+                            let dragged_to = response
+                                .interact_pointer_pos()
+                                .filter(|_| response.dragged() || response.drag_stopped())
+                                .map(|pos| ((pos.x - bar.left()) / bar.width()).clamp(0.0, 1.0));
+                            if let Some(to) = dragged_to {
+                                fraction = to;
+                                if response.drag_stopped() {
+                                    let _ = self.worker.send(WorkerEvent::Seek(to));
+                                }
+                            }
+                            knob = response.dragged() || ui.rect_contains_pointer(full_rect);
+                        }
+                        progress.paint(ui, ui.max_rect(), fraction, knob, current.playing);
+                    }
+                    if self.show_lyrics {
+                        // The lyric line takes the bottom of the window; the track goes above.
+                        let rect = ui.max_rect();
+                        let line = current
+                            .lyrics
+                            .as_deref()
+                            .and_then(|lyrics| lyric_line(lyrics, current.position.as_ref()));
+                        if let Some(line) = line {
+                            ui.painter().text(
+                                egui::pos2(rect.left() + 5.0, rect.bottom() - LYRICS_HEIGHT / 2.0),
+                                egui::Align2::LEFT_CENTER,
+                                line,
+                                FontId::proportional(LYRICS_HEIGHT * 0.7),
+                                self.fg_color.gamma_multiply(0.7),
+                            );
+                        }
+                        ui.set_max_height(rect.height() - LYRICS_HEIGHT);
+                    }
+                    // Each row gets an equal share of the height, in order from the top.
+                    let area = ui.available_rect_before_wrap();
+                    let row_height = area.height() / self.lines.len() as f32;
+                    for (index, line) in self.lines.iter().enumerate() {
+                        let mut segments = line.template.render(current);
+                        for segment in &mut segments {
+                            segment.text = self.text_transform.apply(&segment.text);
+                        }
+                        // The hint and the prefix and suffix go with the first row.
+                        if index == 0 {
+                            if let Some(ms) = current.left_off_at {
+                                segments.push(Segment {
+                                    text: format!(" · left off at {}", clock_time(ms)),
+                                    color: Some(self.artist_color),
+                                    time: false,
+                                });
+                            }
+                            add_affixes(&mut segments, &self.prefix, &self.suffix);
+                        }
+                        let rect = egui::Rect::from_min_size(
+                            egui::pos2(area.left(), area.top() + row_height * index as f32),
+                            egui::vec2(area.width(), row_height),
+                        );
+                        let row_time = self.track_row(ctx, ui, rect, line, segments, index == 0);
+                        time_rect = time_rect.or(row_time);
+                    }
+                } else if bus_down {
+                    self.status_line(ui, &self.error_text, self.error_color);
+                } else if !self.idle_text.is_empty() {
+                    self.status_line(ui, &self.idle_text, self.fg_color);
+                }
+
+                // Added last so it sits above the labels and receives their clicks too.
+                let response = ui.interact(
+                    full_rect,
+                    ui.id().with("click_actions"),
+                    egui::Sense::click(),
+                );
+                // A click on the time switches it between elapsed and remaining instead.
+                let on_time = response
+                    .interact_pointer_pos()
+                    .is_some_and(|pos| time_rect.is_some_and(|rect| rect.contains(pos)));
+                if on_time && response.clicked() {
+                    for line in &mut self.lines {
+                        line.template.count_down = !line.template.count_down;
+                    }
+                } else {
+                    self.handle_clicks(ctx, &response);
+                }
+            });
+
+        if let Some(settings) = &mut self.settings {
+            let (open, saved) = settings.show(ctx);
+            if !open {
+                self.settings = None;
+            }
+            if let Some(saved) = saved {
+                self.apply_settings(ctx, saved);
+            }
+        }
+        // Request repaint to allow for updates from the D-Bus thread
+        let mut repaint_after = Duration::from_millis(500);
+        if fade_progress.is_some() {
+            repaint_after = Duration::ZERO;
+        }
+        if let Some(at) = self.copied_at.filter(|_| copied) {
+            // Come back to take the confirmation down on time.
+            let remaining = Duration::from_secs_f64(COPIED_DURATION - (now - at));
+            repaint_after = repaint_after.min(remaining);
+        }
+        let has_position = current.as_ref().is_some_and(|c| c.position.is_some());
+        // The time only changes once a second, but redraw often enough to tick over on time.
+        if self.uses_token("time") && has_position {
+            repaint_after = repaint_after.min(Duration::from_millis(250));
+        }
+        // A live position needs redrawing every frame (or as often as max_fps allows).
+        if (self.progress.is_some()
+            || self.progress_tint.is_some()
+            || self.show_lyrics
+            || self.uses_token("position_ms"))
+            && has_position
+        {
+            repaint_after = Duration::ZERO;
+        }
+        if eq_bouncing {
+            repaint_after = repaint_after.min(Duration::from_millis(33));
+        }
+        #[cfg(feature = "audio-meter")]
+        if self.audio_meter.is_some() {
+            repaint_after = Duration::from_millis(50);
+        }
+        ctx.request_repaint_after(repaint_after.max(self.min_frame_time));
+    }
+}
+
+/// Draws the `eq_icon` bars at `clock` seconds into their bounce. Purely decorative; each bar
+/// runs its own sine wave, so together they never settle into an obvious loop.
+fn paint_eq_icon(ui: &egui::Ui, rect: egui::Rect, clock: f64, color: Color32) {
+    const BARS: [(f64, f64); 3] = [(7.0, 0.0), (9.3, 1.7), (5.9, 3.1)];
+    let width = rect.width() / (BARS.len() as f32 * 1.5 - 0.5);
+    for (i, (speed, phase)) in BARS.iter().enumerate() {
+        let level = 0.3 + 0.7 * (0.5 + 0.5 * (clock * speed + phase).sin()) as f32;
+        let left = rect.left() + i as f32 * width * 1.5;
+        let bar = egui::Rect::from_min_max(
+            egui::pos2(left, rect.bottom() - rect.height() * level),
+            egui::pos2(left + width, rect.bottom()),
+        );
+        ui.painter().rect_filled(bar, 1.0, color);
+    }
+}
+
+/// Whether the first strongly directional character in `text` is from a right-to-left script.
+fn is_rtl_text(text: &str) -> bool {
+    text.chars()
+        .find(|c| c.is_alphabetic())
+        .is_some_and(|c| {
+            matches!(c as u32,
+                0x0590..=0x08FF // Hebrew, Arabic, Syriac, Thaana, NKo, Samaritan, Mandaic
+                | 0xFB1D..=0xFDFF // Hebrew and Arabic presentation forms
+                | 0xFE70..=0xFEFF)
+        })
+}
+
+fn extract_string_metadata(metadata: &HashMap<String, Value>, key: &str) -> Option<String> {
+    metadata.get(key).and_then(|value| {
+        OwnedValue::try_from(value)
+            .ok()
+            .and_then(|owned_value| TryInto::<String>::try_into(owned_value).ok())
+    })
+}
+
+/// Renders a metadata value as display text. Arrays are joined with ", ".
+fn value_to_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Str(s) => Some(s.to_string()),
+        Value::ObjectPath(path) => Some(path.to_string()),
+        Value::U8(n) => Some(n.to_string()),
+        Value::I16(n) => Some(n.to_string()),
+        Value::U16(n) => Some(n.to_string()),
+        Value::I32(n) => Some(n.to_string()),
+        Value::U32(n) => Some(n.to_string()),
+        Value::I64(n) => Some(n.to_string()),
+        Value::U64(n) => Some(n.to_string()),
+        Value::F64(n) => Some(n.to_string()),
+        Value::Value(inner) => value_to_text(inner),
+        Value::Array(array) => {
+            let items: Vec<String> = array.inner().iter().filter_map(value_to_text).collect();
+            (!items.is_empty()).then(|| items.join(", "))
+        }
+        _ => None,
+    }
+}
+
+/// Trims `text` and collapses runs of spaces, tabs and line breaks into a single space. Other
+/// whitespace, such as non-breaking spaces, is left alone as it's likely intentional.
+fn collapse_whitespace(text: &str) -> String {
+    text.split([' ', '\t', '\n', '\r'])
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Trims an ISO 8601 timestamp such as `2019-05-03T00:00:00Z` down to its date.
+fn format_date(date: &str) -> String {
+    date.split('T').next().unwrap_or(date).to_string()
+}
+
+/// Returns the metadata key a token reads from, honoring the `field_map` config table.
+fn mapped_key<'a>(field_map: &'a HashMap<String, String>, token: &str, default: &'a str) -> &'a str {
+    field_map.get(token).map_or(default, String::as_str)
+}
+
+fn extract_metadata_fields(
+    metadata: &HashMap<String, Value>,
+    field_map: &HashMap<String, String>,
+) -> HashMap<&'static str, String> {
+    let mut fields: HashMap<&'static str, String> = METADATA_TOKENS
+        .iter()
+        .filter_map(|(token, key)| {
+            let key = mapped_key(field_map, token, key);
+            let text = metadata.get(key).and_then(value_to_text)?;
+            let text = if *token == "contentCreated" {
+                format_date(&text)
+            } else {
+                text
+            };
+            Some((*token, text))
+        })
+        .collect();
+    if let Some(station) = extract_station(metadata, field_map) {
+        fields.insert("station", station);
+    }
+    let rating_key = mapped_key(field_map, "rating", "xesam:userRating");
+    let rating = match metadata.get(rating_key) {
+        Some(Value::F64(rating)) => Some(*rating),
+        // Fall back to the player's automatic rating when the user hasn't rated the track.
+        _ => match metadata.get("xesam:autoRating") {
+            Some(Value::F64(rating)) if !field_map.contains_key("rating") => Some(*rating),
+            _ => None,
+        },
+    };
+    if let Some(rating) = rating {
+        fields.insert("rating", rating_stars(rating));
+    }
+    fields
+}
+
+/// Renders a 0.0–1.0 rating as five stars, e.g. 0.6 -> "★★★☆☆".
+fn rating_stars(rating: f64) -> String {
+    let filled = (rating.clamp(0.0, 1.0) * 5.0).round() as usize;
+    "★".repeat(filled) + &"☆".repeat(5 - filled)
+}
+
+/// Finds an internet radio station name. Without a `field_map` entry this only applies to
+/// streams (an http(s) `xesam:url` and no track length), where many players put the station
+/// in `xesam:album`; the stream's host name is the last resort.
+fn extract_station(
+    metadata: &HashMap<String, Value>,
+    field_map: &HashMap<String, String>,
+) -> Option<String> {
+    if let Some(key) = field_map.get("station") {
+        return metadata.get(key).and_then(value_to_text);
+    }
+
+    let url = metadata.get("xesam:url").and_then(value_to_text)?;
+    let host = url
+        .strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"))?
+        .split(['/', ':', '?'])
+        .next()?;
+    let has_length = metadata
+        .get("mpris:length")
+        .and_then(value_to_text)
+        .is_some_and(|length| length != "0");
+    if has_length {
+        return None;
+    }
+
+    metadata
+        .get("xesam:album")
+        .and_then(value_to_text)
+        .filter(|album| !album.is_empty())
+        .or_else(|| Some(host.to_string()))
+}
+
+fn extract_artist_metadata(metadata: &HashMap<String, Value>, key: &str) -> Option<String> {
+    metadata.get(key).and_then(|value| {
+        OwnedValue::try_from(value)
+            .ok()
+            .and_then(|owned_value| {
+                // Try to extract as Vec<String> first (most common case)
+                if let Ok(clone_value) = owned_value.try_clone() {
+                    if let Ok(artists_vec) = TryInto::<Vec<String>>::try_into(clone_value) {
+                        return artists_vec.first().cloned();
+                    }
+                }
+                // Fallback to single string
+                TryInto::<String>::try_into(owned_value).ok()
+            })
+    })
+}
+
+/// Returns a display name for a player, preferring the root interface's `Identity` property.
+fn player_identity(connection: &Connection, service_name: &str) -> String {
+    Proxy::new(
+        connection,
+        service_name,
+        "/org/mpris/MediaPlayer2",
+        "org.mpris.MediaPlayer2",
+    )
+    .and_then(|proxy| proxy.get_property::<String>("Identity"))
+    .ok()
+    .filter(|identity| !identity.is_empty())
+    .unwrap_or_else(|| identity_from_bus_name(service_name))
+}
+
+/// Derives a name from the bus name suffix, e.g. `org.mpris.MediaPlayer2.chromium.instance_1_42` -> "Chromium".
+fn identity_from_bus_name(service_name: &str) -> String {
+    let name = service_name
+        .trim_start_matches("org.mpris.MediaPlayer2.")
+        .split('.')
+        .find(|part| !part.is_empty() && !part.starts_with("instance"))
+        .unwrap_or(service_name);
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Holds an `org.freedesktop.ScreenSaver` inhibition while a followed player is playing.
+#[derive(Default)]
+struct IdleInhibitor {
+    active: Option<(Connection, u32)>,
+}
+
+impl IdleInhibitor {
+    fn screensaver_proxy(connection: &Connection) -> Result<Proxy<'static>, zbus::Error> {
+        Proxy::new(
+            connection,
+            "org.freedesktop.ScreenSaver",
+            "/org/freedesktop/ScreenSaver",
+            "org.freedesktop.ScreenSaver",
+        )
+    }
+
+    fn inhibit(&mut self, connection: &Connection) {
+        if self.active.is_some() {
+            return;
+        }
+        let cookie = Self::screensaver_proxy(connection).and_then(|proxy| {
+            proxy
+                .call_method("Inhibit", &("dbus-now-playing", "Media is playing"))?
+                .body()
+                .deserialize::<u32>()
+        });
+        match cookie {
+            Ok(cookie) => self.active = Some((connection.clone(), cookie)),
+            Err(e) => eprintln!("Failed to inhibit screensaver: {}", e),
+        }
+    }
+
+    fn release(&mut self) {
+        if let Some((connection, cookie)) = self.active.take() {
+            let result = Self::screensaver_proxy(&connection)
+                .and_then(|proxy| proxy.call_method("UnInhibit", &(cookie,)).map(|_| ()));
+            if let Err(e) = result {
+                eprintln!("Failed to release screensaver inhibition: {}", e);
+            }
+        }
+    }
+}
+
+fn playback_status(connection: &Connection, service_name: &str) -> Option<String> {
+    Proxy::new(
+        connection,
+        service_name,
+        "/org/mpris/MediaPlayer2",
+        "org.mpris.MediaPlayer2.Player",
+    )
+    .and_then(|proxy| proxy.get_property::<String>("PlaybackStatus"))
+    .ok()
+}
+
+/// Lists the bus names of all MPRIS players.
+fn list_players(connection: &Connection) -> Result<Vec<String>, zbus::Error> {
+    let proxy = Proxy::new(
+        connection,
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+    )?;
+
+    let all_names: Vec<String> = proxy.call_method("ListNames", &())?.body().deserialize()?;
+
+    Ok(all_names
+        .into_iter()
+        .filter(|name| name.starts_with("org.mpris.MediaPlayer2."))
+        .collect())
+}
+
+/// Finds a player whose bus name contains `pattern` (case-insensitive), preferring one that
+/// is playing.
+fn find_matching_player(
+    connection: &Connection,
+    pattern: &str,
+) -> Result<Option<String>, zbus::Error> {
+    let pattern = pattern.to_lowercase();
+    let matches: Vec<String> = list_players(connection)?
+        .into_iter()
+        .filter(|name| {
+            name.trim_start_matches("org.mpris.MediaPlayer2.")
+                .to_lowercase()
+                .contains(&pattern)
+        })
+        .collect();
+    let playing = matches
+        .iter()
+        .find(|name| playback_status(connection, name).as_deref() == Some("Playing"));
+    Ok(playing.or(matches.first()).cloned())
+}
+
+/// Picks a player to follow. `current` is kept for as long as it is still playing and no player
+/// `[schedule]` or `player_priority` ranks higher is, so two players playing at once don't make
+/// the display flip between them.
+#[cfg_attr(not(feature = "audio-focus"), allow(unused_variables))]
+fn discover_player(
+    connection: &Connection,
+    current: Option<&str>,
+    config: &Config,
+    last_active: impl Fn(&str) -> Option<Instant>,
+) -> Result<Option<String>, zbus::Error> {
+    let mpris_players = list_players(connection)?;
+
+    if mpris_players.is_empty() {
+        return Ok(None);
+    }
+
+    // Prefer whichever player the sound server says is making noise.
+    #[cfg(feature = "audio-focus")]
+    if config.follow_audio_focus.unwrap_or(false) {
+        let focused = audio_focus::focused_players(connection, &mpris_players);
+        if let Some(current) = current.filter(|c| focused.iter().any(|f| f == c)) {
+            return Ok(Some(current.to_string()));
+        }
+        if let Some(first) = focused.into_iter().next() {
+            return Ok(Some(first));
+        }
+    }
+
+    let mut playing = Vec::new();
+    let mut paused_player = None;
+    for player_name in &mpris_players {
+        match playback_status(connection, player_name).as_deref() {
+            Some("Playing") => playing.push(player_name.clone()),
+            Some("Paused") if paused_player.is_none() => {
+                paused_player = Some(player_name.clone());
+            }
+            _ => {}
+        }
+    }
+
+    // With prefer_audible, a player playing at zero volume only wins when nothing is audible.
+    if config.prefer_audible.unwrap_or(false) {
+        let audible: Vec<String> = playing
+            .iter()
+            .filter(|name| volume(connection, name).is_none_or(|volume| volume > 0.0))
+            .cloned()
+            .collect();
+        if !audible.is_empty() {
+            playing = audible;
+        }
+    }
+
+    // The players `[schedule]` prefers at this time of day come first, in the order given.
+    if let Some(preferred) = config.schedule.as_ref().and_then(schedule::active) {
+        playing = best_ranked(playing, preferred);
+    }
+
+    let strategy = config
+        .multi_playing_strategy
+        .as_deref()
+        .and_then(MultiPlaying::parse)
+        .unwrap_or(if config.player_priority.is_some() {
+            MultiPlaying::Priority
+        } else {
+            MultiPlaying::First
+        });
+    if let MultiPlaying::Priority = strategy {
+        let priority = config.player_priority.as_deref().unwrap_or_default();
+        playing = best_ranked(playing, priority);
+    }
+
+    // Stay with the current player while it's still a candidate, which it no longer is once a
+    // player that ranks higher plays.
+    if let Some(current) = current.filter(|current| playing.iter().any(|name| name == current)) {
+        return Ok(Some(current.to_string()));
+    }
+
+    let chosen = match strategy {
+        // `playing` only holds the best ranked players by now.
+        MultiPlaying::First | MultiPlaying::Priority => playing.into_iter().next(),
+        MultiPlaying::Loudest => playing.into_iter().rev().max_by(|a, b| {
+            // Players that don't report a volume are taken to be at full volume.
+            let a = volume(connection, a).unwrap_or(1.0);
+            let b = volume(connection, b).unwrap_or(1.0);
+            a.total_cmp(&b)
+        }),
+        MultiPlaying::MostRecent => playing.into_iter().rev().max_by_key(|name| last_active(name)),
+    };
+
+    Ok(chosen
+        .or(paused_player)
+        .or_else(|| mpris_players.first().cloned()))
+}
+
+/// The players matching the earliest of `patterns` (parts of bus names, in any case) that any
+/// of them match, in their original order. All of them when none match.
+fn best_ranked(players: Vec<String>, patterns: &[String]) -> Vec<String> {
+    let rank = |name: &String| {
+        let name = name.to_lowercase();
+        patterns
+            .iter()
+            .position(|pattern| name.contains(&pattern.to_lowercase()))
+    };
+    match players.iter().filter_map(rank).min() {
+        Some(best) => players
+            .into_iter()
+            .filter(|name| rank(name) == Some(best))
+            .collect(),
+        None => players,
+    }
+}
+
+/// `multi_playing_strategy`: which player discovery picks when several are playing.
+#[derive(Clone, Copy)]
+enum MultiPlaying {
+    /// The first in bus order.
+    First,
+    /// The one with the highest `Volume`.
+    Loudest,
+    /// The earliest match in `player_priority`.
+    Priority,
+    /// The one that changed most recently, e.g. by starting to play or changing track.
+    MostRecent,
+}
+
+impl MultiPlaying {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "first" => Some(Self::First),
+            "loudest" => Some(Self::Loudest),
+            "priority" => Some(Self::Priority),
+            "most_recent" => Some(Self::MostRecent),
+            _ => None,
+        }
+    }
+}
+
+/// The player's `Volume`, if it reports one.
+fn volume(connection: &Connection, service_name: &str) -> Option<f64> {
+    Proxy::new(
+        connection,
+        service_name,
+        "/org/mpris/MediaPlayer2",
+        "org.mpris.MediaPlayer2.Player",
+    )
+    .and_then(|proxy| proxy.get_property::<f64>("Volume"))
+    .ok()
+}
+
+/// Loads and validates the config, printing the effective settings. Returns the exit code.
+fn check_config(args: &Args) -> i32 {
+    let mut builder = ConfigBuilder::new();
+    if let Err(e) = builder.file(args.config.as_deref(), args.profile.as_deref()) {
+        eprintln!("error: {}", e);
+        return 1;
+    }
+    builder.env();
+    builder.overrides(args.overrides.clone());
+    let config = match builder.build() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        }
+    };
+
+    match toml::to_string_pretty(&config) {
+        Ok(text) => {
+            println!("# Options not listed use their built-in defaults.");
+            print!("{}", text);
+        }
+        Err(e) => eprintln!("Failed to serialize config: {}", e),
+    }
+
+    let errors = config.validate();
+    for error in &errors {
+        eprintln!("error: {}", error);
+    }
+    if errors.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+/// `no_session_bus`: what to do when there is no session bus to connect to at all.
+#[derive(Clone, Copy, PartialEq)]
+enum NoSessionBus {
+    /// Show the error state and keep trying to connect.
+    Retry,
+    /// Show the error state once, without retrying.
+    Error,
+    /// Exit straight away.
+    Exit,
+}
+
+impl NoSessionBus {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "retry" => Some(Self::Retry),
+            "error" => Some(Self::Error),
+            "exit" => Some(Self::Exit),
+            _ => None,
+        }
+    }
+}
+
+/// Whether this session has no bus, as on CI machines and in most containers: nothing says
+/// where one is and connecting fails. zbus also tries `$XDG_RUNTIME_DIR/bus` on its own.
+fn session_bus_missing() -> bool {
+    std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() && Connection::session().is_err()
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    if args.init {
+        std::process::exit(init_config(args.config.as_deref(), args.force));
+    }
+    if args.check_config {
+        std::process::exit(check_config(&args));
+    }
+    if args.kill {
+        std::process::exit(instance::kill(args.profile.as_deref()));
+    }
+    #[cfg(unix)]
+    let signals = signals::install();
+    let config = Config::load(&args);
+    if !config.allowed_in_current_desktop() {
+        return Ok(());
+    }
+    let no_session_bus = config
+        .no_session_bus
+        .as_deref()
+        .and_then(NoSessionBus::parse)
+        .unwrap_or(NoSessionBus::Retry);
+    let bus_missing = no_session_bus != NoSessionBus::Retry && session_bus_missing();
+    if bus_missing && no_session_bus == NoSessionBus::Exit {
+        eprintln!("No D-Bus session bus (DBUS_SESSION_BUS_ADDRESS is unset), exiting.");
+        return Ok(());
+    }
+    // Only the window is limited to one instance; any number of --watch/--follow/--tui can run.
+    let _instance = if args.watch || args.follow || args.tui || bus_missing {
+        None
+    } else {
+        match instance::claim(args.profile.as_deref(), args.replace) {
+            Ok(instance::Claim::Owned(connection)) => Some(connection),
+            Ok(instance::Claim::Running) => {
+                eprintln!("Already running; pass --replace to take over or --kill to stop it.");
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Failed to check for a running instance: {}", e);
+                None
+            }
+        }
+    };
+    if config.strict_player.unwrap_or(false)
+        && config.dbus_service.is_none()
+        && args.player.is_none()
+    {
+        eprintln!("strict_player is set without dbus_service or --player; no player will be followed.");
+    }
+    if let Some(name) = config.theme.as_deref().filter(|name| theme(name).is_none()) {
+        eprintln!("Unknown theme '{}', ignoring", name);
+    }
+    let current = config.state_file().and_then(|path| sticky::load(&path));
+    let shared = Arc::new(Mutex::new(AppState {
+        current,
+        bus_error: bus_missing.then(|| "DBUS_SESSION_BUS_ADDRESS is unset".to_string()),
+    }));
+
+    let inhibitor = config
+        .inhibit_idle
+        .unwrap_or(false)
+        .then(|| Arc::new(Mutex::new(IdleInhibitor::default())));
+
+    let frozen = Arc::new(AtomicBool::new(false));
+    let mut worker = Worker::new(
+        Arc::clone(&shared),
+        config.clone(),
+        args.player.clone(),
+        inhibitor.clone(),
+        Arc::clone(&frozen),
+    );
+    // The JSON events carry the position whatever the window would show.
+    if args.watch || cfg!(feature = "mqtt") && config.mqtt_broker.is_some() {
+        worker.always_read_position();
+    }
+    let worker_tx = worker.sender();
+    // Without a bus the worker would only retry forever; leave the error state showing.
+    if bus_missing {
+        eprintln!("No D-Bus session bus (DBUS_SESSION_BUS_ADDRESS is unset), not retrying.");
+    } else {
+        thread::spawn(move || worker.run());
+    }
+
+    #[cfg(feature = "mqtt")]
+    if let Some(broker) = &config.mqtt_broker {
+        let topic = config
+            .mqtt_topic
+            .clone()
+            .unwrap_or_else(|| "dbus-now-playing".to_string());
+        mqtt::spawn(Arc::clone(&shared), broker.clone(), topic);
+    }
+    #[cfg(not(feature = "mqtt"))]
+    if config.mqtt_broker.is_some() {
+        eprintln!("mqtt_broker is set, but this build lacks the 'mqtt' feature. Ignoring.");
+    }
+
+    let idle_text = config
+        .idle_text
+        .clone()
+        .unwrap_or_else(|| "No media playing".to_string());
+    let error_text = config
+        .error_text
+        .clone()
+        .unwrap_or_else(|| "Can't reach D-Bus".to_string());
+    let error_color = config
+        .error_color
+        .as_deref()
+        .map_or(DEFAULT_ERROR_COLOR, |color| {
+            Config::parse_color_or(color, DEFAULT_ERROR_COLOR)
+        });
+    let mut template = config.template();
+    for error in &template.errors {
+        eprintln!("Warning: {}", error);
+    }
+    let artist_color = config
+        .artist_color
+        .as_deref()
+        .map_or(DEFAULT_ARTIST_COLOR, |color| {
+            Config::parse_color_or(color, DEFAULT_ARTIST_COLOR)
+        });
+    template.set_default_color("artist", artist_color);
+    let text_transform = match config.text_transform.as_deref() {
+        None => TextTransform::None,
+        Some(name) => TextTransform::parse(name).unwrap_or_else(|| {
+            eprintln!("Unknown text_transform '{}', ignoring", name);
+            TextTransform::None
+        }),
+    };
+
+    if args.watch || args.follow || args.tui {
+        #[cfg(unix)]
+        if let Some(signals) = signals {
+            signals.listen(worker_tx.clone(), None);
+        }
+        if args.tui {
+            tui::run(shared, &template, text_transform, &idle_text, &error_text, error_color);
+        } else if args.follow {
+            watch::follow(shared, &template);
+        } else {
+            watch::run(shared);
+        }
+        if let Some(inhibitor) = inhibitor {
+            inhibitor.lock().unwrap().release();
+        }
+        return Ok(());
+    }
+
+    let prefix = config.prefix.clone().unwrap_or_default();
+    let suffix = config.suffix.clone().unwrap_or_default();
+    let idle_text = if config.affixes_when_idle.unwrap_or(false) && !idle_text.is_empty() {
+        format!("{}{}{}", prefix, idle_text, suffix)
+    } else {
+        idle_text
+    };
+    let fg_color_parsed = config.fg();
+    let lines = match config.lines.as_ref().filter(|lines| !lines.is_empty()) {
+        None => vec![Line {
+            template,
+            align: None,
+            color: None,
+        }],
+        Some(lines) => lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                let mut template = config.template_for(line.format());
+                for error in &template.errors {
+                    eprintln!("Warning: lines[{}]: {}", index, error);
+                }
+                template.set_default_color("artist", artist_color);
+                Line {
+                    template,
+                    align: line.align().and_then(parse_align),
+                    color: line
+                        .color()
+                        .map(|color| Config::parse_color_or(color, fg_color_parsed)),
+                }
+            })
+            .collect(),
+    };
+    let bg_color_parsed = config.bg();
+    // Either option turns the border on; the other defaults to the text color or 1px.
+    let border = if config.border_color.is_some() || config.border_width.is_some() {
+        let color = config
+            .border_color
+            .as_deref()
+            .map_or(fg_color_parsed, |color| Config::parse_color_or(color, fg_color_parsed));
+        egui::Stroke::new(config.border_width.unwrap_or(1.0), color)
+    } else {
+        egui::Stroke::NONE
+    };
+    // Likewise for the text outline, whose color defaults to the background's, made opaque.
+    let outlined = config.text_outline_color.is_some() || config.text_outline_width.is_some();
+    let text_outline = if outlined {
+        let default_color = bg_color_parsed.to_opaque();
+        let color = config
+            .text_outline_color
+            .as_deref()
+            .map_or(default_color, |color| Config::parse_color_or(color, default_color));
+        egui::Stroke::new(config.text_outline_width.unwrap_or(1.0), color)
+    } else {
+        egui::Stroke::NONE
+    };
+    #[cfg(not(windows))]
+    if config.backdrop_blur.unwrap_or(false) {
+        eprintln!("backdrop_blur is only supported on Windows; use a compositor rule instead.");
+    }
+    #[cfg(not(feature = "audio-focus"))]
+    if config.follow_audio_focus.unwrap_or(false) {
+        eprintln!(
+            "follow_audio_focus is set, but this build lacks the 'audio-focus' feature. Ignoring."
+        );
+    }
+    #[cfg(not(feature = "audio-meter"))]
+    if config.audio_meter.unwrap_or(false) {
+        eprintln!("audio_meter is set, but this build lacks the 'audio-meter' feature. Ignoring.");
+    }
+
+    let min_frame_time = config
+        .max_fps
+        .filter(|fps| *fps > 0)
+        .map_or(Duration::ZERO, |fps| Duration::from_secs_f64(1.0 / fps as f64));
+    let click_actions: HashMap<String, Action> = config
+        .click_actions
+        .iter()
+        .flatten()
+        .filter_map(|(trigger, action)| match Action::parse(action) {
+            Some(action) => Some((trigger.clone(), action)),
+            None => {
+                eprintln!("Unknown click action '{}' for '{}', ignoring", action, trigger);
+                None
+            }
+        })
+        .collect();
+    // A faded-out window would have to be clicked blind, so clicks win.
+    let fade_on_hover = config.fade_on_hover.unwrap_or(false) && click_actions.is_empty();
+    if config.fade_on_hover.unwrap_or(false) && !fade_on_hover {
+        eprintln!("fade_on_hover is ignored while click_actions are set.");
+    }
+    let valign = match config.valign.as_deref() {
+        None => egui::Align::Center,
+        Some(name) => parse_valign(name).unwrap_or_else(|| {
+            eprintln!("Unknown valign '{}', using center", name);
+            egui::Align::Center
+        }),
+    };
+    let progress = config.progress_position.as_deref().and_then(|name| {
+        let Some(position) = ProgressPosition::parse(name) else {
+            eprintln!("Unknown progress_position '{}', not showing progress", name);
+            return None;
+        };
+        // Behind the text a solid fg_color bar would hide it, so default to a faint one.
+        let faint = |color: Color32| match position {
+            ProgressPosition::Background => color.gamma_multiply(0.25),
+            _ => color,
+        };
+        let default_color = faint(fg_color_parsed);
+        let default_paused_color = faint(DEFAULT_PAUSED_COLOR);
+        Some(ProgressBar {
+            position,
+            height: config.progress_height.filter(|height| *height > 0.0).unwrap_or(3.0),
+            color: config
+                .progress_color
+                .as_deref()
+                .map_or(default_color, |color| Config::parse_color_or(color, default_color)),
+            paused_color: config.paused_color.as_deref().map_or(default_paused_color, |color| {
+                Config::parse_color_or(color, default_paused_color)
+            }),
+        })
+    });
+    let progress_tint = config.progress_tint.unwrap_or(false).then(|| {
+        let color = |value: &Option<String>, default: Color32| {
+            value
+                .as_deref()
+                .map_or(default, |color| Config::parse_color_or(color, default))
+        };
+        ProgressTint {
+            start: color(&config.progress_tint_start, fg_color_parsed.gamma_multiply(0.05)),
+            end: color(&config.progress_tint_end, fg_color_parsed.gamma_multiply(0.25)),
+        }
+    });
+    let force_opaque = config.force_opaque.unwrap_or(false);
+    let bg_image = config
+        .bg_image
+        .as_deref()
+        .and_then(|path| load_bg_image(&expand_path(path)));
+    let fullscreen_active = config
+        .hide_over_fullscreen
+        .unwrap_or(false)
+        .then(fullscreen::spawn_watcher);
+    let font_size_range = match config.font_size_range() {
+        (min, max) if min > 0.0 && min <= max => (min, max),
+        (min, max) => {
+            eprintln!(
+                "Invalid font size range {}..{}, using the defaults",
+                min, max
+            );
+            Config::default().font_size_range()
+        }
+    };
+    let show_lyrics = config.show_lyrics.unwrap_or(false);
+    let window_width = 400.0;
+    let rows_height = 25.0 * lines.len() as f32;
+    let window_height = if show_lyrics { rows_height + LYRICS_HEIGHT } else { rows_height };
+    let (origin_x, origin_y) = match config.monitor.as_deref() {
+        None => (0, 0),
+        Some(wanted) => match monitor::find(wanted) {
+            Some(monitor) => (monitor.x, monitor.y),
+            None => {
+                eprintln!("Monitor '{}' not found, positioning on the whole screen", wanted);
+                (0, 0)
+            }
+        },
+    };
+    let window_x = (origin_x + config.window_x.unwrap_or(0)) as f32;
+    let window_y = (origin_y + config.window_y.unwrap_or(1000)) as f32;
+    
+    //println!("Attempting to position window at: x={}, y={}", window_x, window_y);
+    
+    let mut viewport = egui::ViewportBuilder::default()
+        .with_title(config.window_title.as_deref().unwrap_or("Now Playing"))
+        .with_inner_size([window_width, window_height])
+        .with_position([window_x, window_y])
+        .with_decorations(false)
+        .with_always_on_top()
+        .with_resizable(false)
+        .with_transparent(!force_opaque)
+        .with_taskbar(false)
+        .with_has_shadow(!config.no_shadow.unwrap_or(false))
+        .with_visible(true);
+    if let Some(app_id) = &config.app_id {
+        viewport = viewport.with_app_id(app_id);
+    }
+    let native_options = NativeOptions {
+        viewport,
+        ..Default::default()
+    };
 
     eframe::run_native(
         "Now Playing",
         native_options,
-        Box::new(move |_cc| {
+        Box::new(move |cc| {
+            #[cfg(windows)]
+            if config.backdrop_blur.unwrap_or(false) {
+                backdrop::request_blur(cc);
+            }
+            #[cfg(unix)]
+            if let Some(signals) = signals {
+                signals.listen(worker_tx.clone(), Some(cc.egui_ctx.clone()));
+            }
+            let bg_image = bg_image.map(|image| {
+                let tile = config.bg_image_mode.as_deref() == Some("tile");
+                let options = if tile {
+                    egui::TextureOptions::LINEAR_REPEAT
+                } else {
+                    egui::TextureOptions::LINEAR
+                };
+                BgImage {
+                    texture: cc.egui_ctx.load_texture("bg_image", image, options),
+                    tile,
+                    tint: Color32::WHITE.gamma_multiply(config.bg_image_opacity.unwrap_or(1.0)),
+                }
+            });
             Ok(Box::new(NowPlayingApp {
                 shared,
                 fg_color: fg_color_parsed,
                 bg_color: bg_color_parsed,
+                corner_radius: if force_opaque {
+                    0.0
+                } else {
+                    config.corner_radius.unwrap_or(0.0)
+                },
+                border,
+                text_outline,
+                opaque: force_opaque,
+                idle_text,
+                prefix,
+                suffix,
+                error_text,
+                error_color,
+                lines,
+                text_transform,
+                font_size_range,
+                fixed_font_size: config.font_size.filter(|size| *size > 0.0),
+                bg_image,
+                progress,
+                progress_tint,
+                min_frame_time,
+                click_actions,
+                worker: worker_tx,
+                rtl: config.rtl,
+                valign,
+                fullscreen_active,
+                lowered: false,
+                frozen,
+                fade_on_hover,
+                show_lyrics,
+                eq_icon: config.eq_icon.unwrap_or(false),
+                eq_clock: 0.0,
+                copy_template: config.copy_format.as_deref().map(Template::parse),
+                copied_at: None,
+                fade: Duration::from_millis(config.fade_ms.unwrap_or(0).into()),
+                showing_track: None,
+                fade_started: None,
+                refresh_on_show: config.refresh_on_show.unwrap_or(true),
+                was_visible: None,
+                was_focused: false,
+                config_path: config_path(args.config.as_deref()),
+                monitor_origin: (origin_x, origin_y),
+                artist_color,
+                settings: None,
+                #[cfg(feature = "audio-meter")]
+                audio_meter: config
+                    .audio_meter
+                    .unwrap_or(false)
+                    .then(audio_meter::AudioMeter::spawn),
+                config,
             }))
         }),
     )?;
 
+    if let Some(inhibitor) = inhibitor {
+        inhibitor.lock().unwrap().release();
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(title: &str, artist: &str) -> NowPlaying {
+        NowPlaying {
+            title: title.to_string(),
+            artist: artist.to_string(),
+            player: String::new(),
+            fields: HashMap::new(),
+            position: None,
+            lyrics: None,
+            art_url: None,
+            playing: true,
+            left_off_at: None,
+        }
+    }
+
+    fn text(segments: &[Segment]) -> String {
+        segments.iter().map(|segment| segment.text.as_str()).collect()
+    }
+
+    #[test]
+    fn affixes_wrap_the_rendered_track() {
+        let template = Template::parse("{artist} - {title}");
+        let mut segments = template.render(&track("Title", "Artist"));
+        add_affixes(&mut segments, "[", "]");
+        assert_eq!(text(&segments), "[Artist - Title]");
+    }
+
+    #[test]
+    fn empty_affixes_add_nothing() {
+        let template = Template::parse("{artist} - {title}");
+        let mut segments = template.render(&track("Title", "Artist"));
+        add_affixes(&mut segments, "", "]");
+        assert_eq!(text(&segments), "Artist - Title]");
+        add_affixes(&mut segments, "[", "");
+        assert_eq!(text(&segments), "[Artist - Title]");
+    }
+
+    fn colors(segments: &[Segment]) -> Vec<Option<Color32>> {
+        segments.iter().map(|segment| segment.color).collect()
+    }
+
+    #[test]
+    fn tokens_render_the_track_between_literals() {
+        let template = Template::parse("Now: {title} by {artist}!");
+        assert!(template.errors.is_empty());
+        let segments = template.render(&track("Title", "Artist"));
+        assert_eq!(text(&segments), "Now: Title by Artist!");
+        // Empty fields leave no segment behind, but the literals around them stay.
+        let segments = template.render(&track("Title", ""));
+        assert_eq!(text(&segments), "Now: Title by !");
+        assert_eq!(segments.len(), 4);
+    }
+
+    #[test]
+    fn tokens_may_carry_a_color() {
+        let mut template = Template::parse("{title:#FF000080} {artist} {title: #zz}");
+        template.set_default_color("artist", Color32::BLUE);
+        let segments = template.render(&track("Title", "Artist"));
+        assert_eq!(text(&segments), "Title Artist Title");
+        let red = Color32::from_rgba_unmultiplied(255, 0, 0, 0x80);
+        assert_eq!(colors(&segments), [Some(red), None, Some(Color32::BLUE), None, None]);
+        assert_eq!(template.errors, ["format token '{title}' has invalid color ' #zz'"]);
+    }
+
+    #[test]
+    fn unknown_tokens_are_reported_and_render_nothing() {
+        let template = Template::parse("{title}{ nope }|");
+        assert_eq!(template.errors, ["format uses unknown token '{nope}'"]);
+        assert_eq!(text(&template.render(&track("Title", "Artist"))), "Title|");
+    }
+
+    #[test]
+    fn unbalanced_braces_are_kept_as_text() {
+        let cases = [
+            ("{title", "{title"),
+            ("title}", "title}"),
+            ("{title} {", "Title {"),
+            ("}{artist}{", "}Artist{"),
+            ("{title}{{artist", "Title{{artist"),
+            ("", ""),
+        ];
+        for (format, expected) in cases {
+            let template = Template::parse(format);
+            assert!(template.errors.is_empty(), "{}: {:?}", format, template.errors);
+            assert_eq!(text(&template.render(&track("Title", "Artist"))), expected, "{}", format);
+        }
+    }
+
+    #[test]
+    fn playerctl_variables_render_like_our_tokens() {
+        let mut now = track("Title", "Artist");
+        now.player = "Player".to_string();
+        now.fields.insert("album", "Album".to_string());
+        let ours = Template::parse("{title} - {artist} ({album}, {player})").render(&now);
+        for format in [
+            "{{xesam:title}} - {{xesam:artist}} ({{xesam:album}}, {{playerName}})",
+            "{{title}} - {{artist}} ({{album}}, {{ playerName }})",
+            "{{xesam:title}} - {artist} ({{album}}, {player})",
+        ] {
+            let template = Template::parse(format);
+            assert!(template.errors.is_empty(), "{}: {:?}", format, template.errors);
+            assert_eq!(text(&template.render(&now)), text(&ours), "{}", format);
+        }
+    }
+
+    #[test]
+    fn unknown_playerctl_variables_are_reported_and_render_nothing() {
+        let template = Template::parse("{{title}}{{mpris:trackid}}|{{status}}");
+        assert_eq!(
+            template.errors,
+            [
+                "format uses unknown variable '{{mpris:trackid}}'",
+                "format uses unknown variable '{{status}}'",
+            ]
+        );
+        assert_eq!(text(&template.render(&track("Title", "Artist"))), "Title|");
+        assert_eq!(playerctl_token("xesam:genre"), Some("genre"));
+        assert_eq!(playerctl_token("genre"), None);
+    }
+
+    fn table(toml: &str) -> toml::Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    const LAYERED_CONFIG: &str = r##"
+        theme = "solarized"
+        artist_color = "#111111"
+        window_x = 5
+        window_y = 5
+        [click_actions]
+        left = "playpause"
+        right = "settings"
+        [profiles.x]
+        window_y = 7
+        [profiles.x.click_actions]
+        left = "next"
+    "##;
+
+    #[test]
+    fn config_layers_override_each_other_in_order() {
+        let mut builder = ConfigBuilder::new();
+        builder.content(LAYERED_CONFIG, Some("x")).unwrap();
+        builder.vars([
+            ("DBUS_NOW_PLAYING_WINDOW_X".to_string(), "9".to_string()),
+            ("DBUS_NOW_PLAYING_FG_COLOR".to_string(), "#222222".to_string()),
+            ("WINDOW_X".to_string(), "1".to_string()),
+        ]);
+        builder.overrides(table(r##"fg_color = "#333333""##));
+        let config = builder.build().unwrap();
+
+        // Defaults < theme < file < profile < environment < --set.
+        assert_eq!(config.window_title, Config::default().window_title);
+        assert_eq!(config.error_color.as_deref(), Some("#DC322F"));
+        assert_eq!(config.artist_color.as_deref(), Some("#111111"));
+        assert_eq!(config.window_y, Some(7));
+        assert_eq!(config.window_x, Some(9));
+        assert_eq!(config.fg_color, "#333333");
+        // The profile's nested table is merged into the base one, key by key.
+        let actions = config.click_actions.unwrap();
+        assert_eq!(actions.get("left").map(String::as_str), Some("next"));
+        assert_eq!(actions.get("right").map(String::as_str), Some("settings"));
+    }
+
+    #[test]
+    fn the_base_config_applies_without_a_profile_and_a_missing_one_is_an_error() {
+        let mut builder = ConfigBuilder::new();
+        builder.content(LAYERED_CONFIG, None).unwrap();
+        let config = builder.build().unwrap();
+        assert_eq!((config.window_x, config.window_y), (Some(5), Some(5)));
+        let left = config.click_actions.unwrap().remove("left");
+        assert_eq!(left.as_deref(), Some("playpause"));
+
+        let mut builder = ConfigBuilder::new();
+        let missing = builder.content(LAYERED_CONFIG, Some("y"));
+        assert_eq!(missing, Err("profile 'y' not found".to_string()));
+    }
+
+    #[test]
+    fn overrides_are_read_as_toml_or_else_as_strings() {
+        let cases = [
+            ("20", toml::Value::Integer(20)),
+            ("1.5", toml::Value::Float(1.5)),
+            ("true", toml::Value::Boolean(true)),
+            (r#""quoted""#, toml::Value::String("quoted".to_string())),
+            ("#FF0000", toml::Value::String("#FF0000".to_string())),
+            ("two words", toml::Value::String("two words".to_string())),
+            ("", toml::Value::String(String::new())),
+            ("[1, 2]", toml::Value::Array(vec![1.into(), 2.into()])),
+            (r#"{left="next"}"#, toml::Value::Table(table(r#"left = "next""#))),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_override_value(value), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn merged_tables_keep_what_the_overlay_leaves_out() {
+        let mut base = table("a = 1\nb = 2\n[t]\nx = 1\ny = 2\n[u]\nx = 1");
+        merge_tables(&mut base, table("b = 3\nu = 4\n[t]\ny = 5\nz = 6"));
+        assert_eq!(base, table("a = 1\nb = 3\nu = 4\n[t]\nx = 1\ny = 5\nz = 6"));
+    }
+
+    #[test]
+    fn truncate_cuts_to_the_limit_in_characters() {
+        let cases = [
+            ("Title", 5, "…", "Title"),
+            ("Title", 4, "…", "Tit…"),
+            ("Song title", 6, "…", "Song…"),
+            // Multibyte characters are never split at the cut.
+            ("日本語の歌", 4, "…", "日本語…"),
+            ("Café au lait", 5, "…", "Café…"),
+            ("🎵🎶🎵🎶", 3, "…", "🎵🎶…"),
+            ("Title", 4, "...", "T..."),
+            // An ellipsis as long as the limit, or longer, leaves no room, so the text is cut
+            // without one.
+            ("Title", 3, "...", "Tit"),
+            ("Title", 2, "...", "Ti"),
+            ("Title", 0, "…", ""),
+            ("Title", 3, "", "Tit"),
+        ];
+        for (text, max, ellipsis, expected) in cases {
+            assert_eq!(truncate(text, max, ellipsis), expected, "{} to {}", text, max);
+        }
+    }
+
+    #[test]
+    fn lrc_lines_split_into_timestamps_and_text() {
+        let cases = [
+            ("[00:12.50] Line", Some((vec![12_500], "Line"))),
+            ("[01:02]Line", Some((vec![62_000], "Line"))),
+            ("[00:12.00][01:30.00] Chorus", Some((vec![12_000, 90_000], "Chorus"))),
+            ("[00:12.00] [01:30.00]", Some((vec![12_000, 90_000], ""))),
+            ("[00:12.00][ar:Artist] Line", Some((vec![12_000], "[ar:Artist] Line"))),
+            ("[ar:Artist]", None),
+            ("Plain line", None),
+            ("[00:12.00", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(lrc_line(line), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn timed_lyrics_follow_the_position() {
+        let lyrics = "[ti:Song]\n[00:10.00][00:40.00] Chorus\n[00:20.00] Verse\n[00:50.00] End";
+        let at = |ms: i64| Position {
+            micros: ms * 1000,
+            at: Instant::now(),
+            rate: 0.0,
+            length: None,
+            can_seek: false,
+        };
+        let cases = [
+            // Before the first line, that line is shown ahead of time.
+            (0, "Chorus"),
+            (9_999, "Chorus"),
+            (10_000, "Chorus"),
+            (25_000, "Verse"),
+            (45_000, "Chorus"),
+            (60_000, "End"),
+        ];
+        for (ms, expected) in cases {
+            assert_eq!(lyric_line(lyrics, Some(&at(ms))), Some(expected), "at {}", ms);
+        }
+        // Without a position the first line is shown, without its timestamps.
+        let untitled = "[00:10.00][00:40.00] Chorus\n[00:20.00] Verse";
+        assert_eq!(lyric_line(untitled, None), Some("Chorus"));
+    }
+
+    #[test]
+    fn whitespace_runs_collapse_to_one_space() {
+        let cases = [
+            ("  Title  ", "Title"),
+            ("Some \t  Title", "Some Title"),
+            ("Line\r\nbreak\n\nhere", "Line break here"),
+            ("Non\u{a0}\u{a0}breaking", "Non\u{a0}\u{a0}breaking"),
+            (" \u{3000}wide ", "\u{3000}wide"),
+            ("", ""),
+            (" \t\n ", ""),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(collapse_whitespace(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn ratings_round_to_whole_stars() {
+        let cases = [
+            (0.0, "☆☆☆☆☆"),
+            (0.6, "★★★☆☆"),
+            (1.0, "★★★★★"),
+            // Half stars round up, anything less rounds down.
+            (0.1, "★☆☆☆☆"),
+            (0.5, "★★★☆☆"),
+            (0.89, "★★★★☆"),
+            (0.09, "☆☆☆☆☆"),
+            // Out of range ratings are clamped rather than drawn with more or fewer stars.
+            (1.5, "★★★★★"),
+            (-1.0, "☆☆☆☆☆"),
+            (f64::NAN, "☆☆☆☆☆"),
+        ];
+        for (rating, expected) in cases {
+            assert_eq!(rating_stars(rating), expected, "{}", rating);
+        }
+    }
+
+    #[test]
+    fn the_rating_token_reads_the_user_rating_before_the_automatic_one() {
+        let rating = |entries: Vec<(&str, Value<'static>)>, field_map: &[(&str, &str)]| {
+            let metadata = entries.into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+            let field_map = field_map.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            extract_metadata_fields(&metadata, &field_map).remove("rating")
+        };
+        let user = || ("xesam:userRating", Value::F64(7.0));
+        let auto = || ("xesam:autoRating", Value::F64(0.4));
+        assert_eq!(rating(vec![user(), auto()], &[]).unwrap(), "★★★★★");
+        assert_eq!(rating(vec![auto()], &[]).unwrap(), "★★☆☆☆");
+        assert_eq!(rating(vec![("xesam:userRating", Value::from("5"))], &[]), None);
+        // A mapped rating key is read on its own.
+        assert_eq!(rating(vec![auto()], &[("rating", "x:stars")]), None);
+        let mapped = rating(vec![("x:stars", Value::F64(0.2)), user()], &[("rating", "x:stars")]);
+        assert_eq!(mapped.unwrap(), "★☆☆☆☆");
+    }
+
+    #[test]
+    fn stations_come_from_streams() {
+        let url = || ("xesam:url", Value::from("http://radio.example.com:8000/live?x=1"));
+        let host = Some("radio.example.com");
+        let cases = [
+            (vec![url(), ("xesam:album", Value::from("Jazz FM"))], Some("Jazz FM")),
+            (vec![("xesam:url", Value::from("https://radio.example.com/live"))], host),
+            (vec![url()], host),
+            (vec![url(), ("xesam:album", Value::from(""))], host),
+            (vec![url(), ("mpris:length", Value::from(0_i64))], host),
+            // Tracks with a length, local files and tracks without a URL aren't streams.
+            (vec![url(), ("mpris:length", Value::from(1_i64))], None),
+            (vec![("xesam:url", Value::from("file:///music/song.mp3"))], None),
+            (vec![("xesam:album", Value::from("Album"))], None),
+        ];
+        for (entries, expected) in cases {
+            let metadata: HashMap<String, Value> =
+                entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect();
+            let station = extract_station(&metadata, &HashMap::new());
+            assert_eq!(station.as_deref(), expected, "{:?}", metadata);
+        }
+    }
+
+    #[test]
+    fn a_mapped_station_key_is_read_from_any_track() {
+        let metadata = HashMap::from([
+            ("xesam:url".to_string(), Value::from("file:///music/song.mp3")),
+            ("mpris:length".to_string(), Value::from(1_i64)),
+            ("x:station".to_string(), Value::from("Station")),
+        ]);
+        let field_map = |key: &str| HashMap::from([("station".to_string(), key.to_string())]);
+        let station = extract_station(&metadata, &field_map("x:station"));
+        assert_eq!(station.as_deref(), Some("Station"));
+        assert_eq!(extract_station(&metadata, &field_map("x:missing")), None);
+    }
+
+    #[test]
+    fn paths_expand_home_and_variables() {
+        let home = std::env::var("HOME").unwrap();
+        let unset = "DBUS_NOW_PLAYING_TEST_SURELY_UNSET";
+        let cases = [
+            ("~".to_string(), home.clone()),
+            ("~/music".to_string(), format!("{}/music", home)),
+            ("$HOME/music".to_string(), format!("{}/music", home)),
+            ("${HOME}music".to_string(), format!("{}music", home)),
+            ("/a/$HOME.b".to_string(), format!("/a/{}.b", home)),
+            // Unset variables expand to nothing, and a name runs as far as it can.
+            (format!("/a/${}/b", unset), "/a//b".to_string()),
+            (format!("/a/${{{}}}/b", unset), "/a//b".to_string()),
+            ("$HOMEmusic".to_string(), String::new()),
+            // A `$` without a name, or an unclosed `${`, is kept as written.
+            ("/a/$/b$".to_string(), "/a/$/b$".to_string()),
+            ("${HOME/b".to_string(), "${HOME/b".to_string()),
+            // Only a leading `~` is expanded, and only for a user that exists.
+            ("/a/~/b".to_string(), "/a/~/b".to_string()),
+            ("~no-such-user-here/b".to_string(), "~no-such-user-here/b".to_string()),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(expand_path(&path), PathBuf::from(&expected), "{}", path);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn paths_expand_other_users_homes() {
+        assert_eq!(expand_path("~root"), PathBuf::from("/root"));
+        assert_eq!(expand_path("~root/.config"), PathBuf::from("/root/.config"));
+    }
+
+    #[test]
+    fn blank_colors_fall_back_to_the_default() {
+        assert_eq!(Config::parse_color_or("", Color32::RED), Color32::RED);
+        assert_eq!(Config::parse_color_or("   ", Color32::RED), Color32::RED);
+    }
+
+    #[test]
+    fn invalid_colors_are_white() {
+        assert_eq!(Config::parse_color_or("#12345G", Color32::RED), Color32::WHITE);
+        assert_eq!(Config::parse_color_or("#1234", Color32::RED), Color32::WHITE);
+    }
+
+    #[test]
+    fn colors_may_carry_an_alpha() {
+        let color = Config::parse_color_or("#11223344", Color32::RED);
+        assert_eq!(color, Color32::from_rgba_unmultiplied(0x11, 0x22, 0x33, 0x44));
+        let color = Config::parse_color_or("112233", Color32::RED);
+        assert_eq!(color, Color32::from_rgb(0x11, 0x22, 0x33));
+    }
+
+    #[test]
+    fn empty_fg_and_bg_use_their_defaults() {
+        let mut config = Config {
+            fg_color: "   ".to_string(),
+            bg_color: String::new(),
+            ..Config::default()
+        };
+        assert_eq!(config.fg(), Color32::WHITE);
+        assert_eq!(config.bg(), Color32::TRANSPARENT);
+        config.fg_color = "#FF000080".to_string();
+        config.bg_color = "nope".to_string();
+        assert_eq!(config.fg(), Color32::from_rgba_unmultiplied(255, 0, 0, 0x80));
+        assert_eq!(config.bg(), Color32::WHITE);
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn best_ranked_keeps_the_players_matching_the_earliest_pattern() {
+        let players = names(&[
+            "org.mpris.MediaPlayer2.firefox",
+            "org.mpris.MediaPlayer2.spotify",
+            "org.mpris.MediaPlayer2.mpv.instance1",
+            "org.mpris.MediaPlayer2.mpv.instance2",
+        ]);
+        let ranked = best_ranked(players.clone(), &names(&["MPV", "spotify"]));
+        assert_eq!(ranked, players[2..]);
+        let ranked = best_ranked(players.clone(), &names(&["vlc", "spotify"]));
+        assert_eq!(ranked, players[1..2]);
+        assert_eq!(best_ranked(players.clone(), &names(&["vlc"])), players);
+    }
+}
@@ -0,0 +1,42 @@
+//! `monitor`: finds a monitor's geometry so `window_x`/`window_y` can be relative to it.
+//!
+//! Monitors are listed with `xrandr --listmonitors`, so this works on X11 (and XWayland);
+//! Wayland compositors place windows themselves anyway.
+
+use std::process::Command;
+
+/// A monitor's name and position on the virtual screen, in pixels.
+pub struct Monitor {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Finds the monitor matching `wanted`: its index in `xrandr --listmonitors` (0 is the first)
+/// or its output name such as `HDMI-1`.
+pub fn find(wanted: &str) -> Option<Monitor> {
+    let output = Command::new("xrandr").arg("--listmonitors").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let monitors: Vec<Monitor> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .filter_map(parse_line)
+        .collect();
+    match wanted.parse::<usize>() {
+        Ok(index) => monitors.into_iter().nth(index),
+        Err(_) => monitors.into_iter().find(|m| m.name == wanted),
+    }
+}
+
+/// Parses a line such as ` 1: +HDMI-1 1920/509x1080/286+2560+0  HDMI-1`.
+fn parse_line(line: &str) -> Option<Monitor> {
+    let mut words = line.split_whitespace().skip(1);
+    let name = words.next()?.trim_start_matches(['+', '*']).to_string();
+    let geometry = words.next()?;
+    let mut offsets = geometry.splitn(3, '+').skip(1);
+    let x = offsets.next()?.parse().ok()?;
+    let y = offsets.next()?.parse().ok()?;
+    Some(Monitor { name, x, y })
+}
@@ -0,0 +1,196 @@
+//! `mqtt_broker`: publishes the `--watch` JSON event for every change to an MQTT topic, retained
+//! so dashboards get the current state as soon as they subscribe.
+//!
+//! This is a minimal MQTT 3.1.1 client that only sends QoS 0 publishes. When the broker goes
+//! away the failed publish is retried on a fresh connection until it succeeds.
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{watch, AppState};
+
+const DEFAULT_PORT: u16 = 1883;
+/// Pause between attempts to reach a broker that is down.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Starts publishing changes to `topic` on `broker` (`host` or `host:port`) in the background.
+pub fn spawn(shared: Arc<Mutex<AppState>>, broker: String, topic: String) {
+    thread::spawn(move || {
+        let mut connection: Option<TcpStream> = None;
+        let mut seq: u64 = 0;
+        let mut failing = false;
+        watch::on_change(&shared, watch::body, |body| {
+            seq += 1;
+            let position = watch::position_ms(&shared.lock().unwrap());
+            let payload = watch::event(seq, body, position);
+            loop {
+                if connection.as_ref().is_some_and(|stream| !is_open(stream)) {
+                    connection = None;
+                }
+                let result = match connection.as_mut() {
+                    Some(stream) => publish(stream, &topic, &payload),
+                    None => connect(&broker).and_then(|mut stream| {
+                        publish(&mut stream, &topic, &payload)?;
+                        connection = Some(stream);
+                        Ok(())
+                    }),
+                };
+                match result {
+                    Ok(()) => {
+                        failing = false;
+                        return true;
+                    }
+                    Err(e) => {
+                        // Only report the first failure of a streak, not every retry.
+                        if !failing {
+                            eprintln!("MQTT publish to {} failed: {}. Retrying...", broker, e);
+                            failing = true;
+                        }
+                        connection = None;
+                        thread::sleep(RECONNECT_INTERVAL);
+                    }
+                }
+            }
+        });
+    });
+}
+
+/// Opens a session with the broker and waits for it to accept.
+fn connect(broker: &str) -> std::io::Result<TcpStream> {
+    let (host, port) = match broker.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().unwrap_or(DEFAULT_PORT)),
+        None => (broker, DEFAULT_PORT),
+    };
+    let mut stream = TcpStream::connect((host, port))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+
+    let client_id = format!("dbus-now-playing-{}", std::process::id());
+    stream.write_all(&connect_packet(&client_id))?;
+
+    let mut connack = [0u8; 4];
+    stream.read_exact(&mut connack)?;
+    if connack[0] != 0x20 || connack[3] != 0 {
+        return Err(std::io::Error::other(format!(
+            "broker refused the connection (code {})",
+            connack[3]
+        )));
+    }
+    Ok(stream)
+}
+
+/// Whether the broker still has the connection open. Writing to a socket the other side has
+/// closed can appear to succeed once, losing that message, so check for the close first.
+fn is_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    // The broker never sends anything unprompted, so readable means closed (or broken).
+    let open = matches!(
+        stream.peek(&mut [0u8; 1]),
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock
+    );
+    stream.set_nonblocking(false).is_ok() && open
+}
+
+/// Sends a retained QoS 0 `PUBLISH`.
+fn publish(stream: &mut TcpStream, topic: &str, payload: &str) -> std::io::Result<()> {
+    stream.write_all(&publish_packet(topic, payload))
+}
+
+/// A clean-session `CONNECT` without keep-alive.
+fn connect_packet(client_id: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_str(&mut body, "MQTT");
+    body.push(4); // Protocol level 3.1.1
+    body.push(0x02); // Clean session
+    body.extend_from_slice(&0u16.to_be_bytes()); // No keep-alive
+    put_str(&mut body, client_id);
+    packet(0x10, &body)
+}
+
+/// A retained QoS 0 `PUBLISH`.
+fn publish_packet(topic: &str, payload: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    put_str(&mut body, topic);
+    body.extend_from_slice(payload.as_bytes());
+    packet(0x31, &body)
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    // Remaining length, seven bits at a time.
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Appends a length-prefixed UTF-8 string.
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaining_length_takes_seven_bits_a_byte() {
+        let cases: [(usize, &[u8]); 7] = [
+            (0, &[0x00]),
+            (127, &[0x7F]),
+            (128, &[0x80, 0x01]),
+            (321, &[0xC1, 0x02]),
+            (16_383, &[0xFF, 0x7F]),
+            (16_384, &[0x80, 0x80, 0x01]),
+            (2_097_152, &[0x80, 0x80, 0x80, 0x01]),
+        ];
+        for (len, expected) in cases {
+            let packet = packet(0x31, &vec![0; len]);
+            assert_eq!(&packet[1..=expected.len()], expected, "{}", len);
+            assert_eq!(packet.len(), 1 + expected.len() + len, "{}", len);
+        }
+    }
+
+    #[test]
+    fn connect_asks_for_a_clean_session() {
+        let expected = [
+            0x10, 16, // CONNECT, remaining length
+            0, 4, b'M', b'Q', b'T', b'T', // Protocol name
+            4,    // Protocol level 3.1.1
+            0x02, // Clean session, no will, username or password
+            0, 0, // No keep-alive
+            0, 4, b't', b'e', b's', b't', // Client identifier
+        ];
+        assert_eq!(connect_packet("test"), expected);
+    }
+
+    #[test]
+    fn publish_is_retained_with_a_length_prefixed_topic() {
+        let expected = [
+            0x31, 11, // PUBLISH with QoS 0 and retain, remaining length
+            0, 5, b'n', b'o', b'w', b'/', b'p', // Topic, and no packet identifier at QoS 0
+            b'{', b'}', b'\n', b'!', // Payload, as it is
+        ];
+        assert_eq!(publish_packet("now/p", "{}\n!"), expected);
+        let topic = "ä".repeat(200);
+        let packet = publish_packet(&topic, "");
+        assert_eq!(packet[..5], [0x31, 0x92, 0x03, 0x01, 0x90]);
+    }
+}
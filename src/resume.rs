@@ -0,0 +1,98 @@
+//! `resume_hint`: remembers how far each track got, so that coming back to one whose player
+//! started it over (as many do with audiobooks and podcasts) shows where it was left off.
+//!
+//! Positions are only kept for as long as the widget runs, for the most recent [`MAX_TRACKS`]
+//! tracks. A player that picks up where it was needs no hint, so none is shown then.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+const MAX_TRACKS: usize = 100;
+/// How long the hint stays up after the track comes back.
+const HINT_DURATION: Duration = Duration::from_secs(10);
+/// How far before the remembered position the track has to come back for a hint.
+const MIN_BEHIND_MS: i64 = 10_000;
+
+#[derive(Default)]
+pub struct ResumeMemory {
+    /// Tracks switched away from, oldest first, with their last position in milliseconds.
+    left: VecDeque<(String, i64)>,
+    /// The track being played and its latest position.
+    current: Option<(String, i64)>,
+    /// Where the current track was left off, and when it came back.
+    hint: Option<(i64, Instant)>,
+}
+
+impl ResumeMemory {
+    /// Records that track `id` is at `ms`. Returns where it was left off while the hint is up.
+    pub fn seen(&mut self, id: &str, ms: i64) -> Option<i64> {
+        if self.current.as_ref().is_none_or(|(current, _)| current != id) {
+            if let Some(left) = self.current.take() {
+                if self.left.len() == MAX_TRACKS {
+                    self.left.pop_front();
+                }
+                self.left.push_back(left);
+            }
+            let earlier = self
+                .left
+                .iter()
+                .position(|(left, _)| left == id)
+                .and_then(|index| self.left.remove(index))
+                .map(|(_, at)| at);
+            self.hint = earlier
+                .filter(|at| at - ms >= MIN_BEHIND_MS)
+                .map(|at| (at, Instant::now()));
+        }
+        self.current = Some((id.to_string(), ms));
+        self.hint
+            .filter(|(_, since)| since.elapsed() < HINT_DURATION)
+            .map(|(at, _)| at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A memory that has seen `count` tracks, named by number, each left off a minute in.
+    fn memory_of(count: usize) -> ResumeMemory {
+        let mut memory = ResumeMemory::default();
+        for track in 0..count {
+            assert_eq!(memory.seen(&track.to_string(), 60_000), None);
+        }
+        memory
+    }
+
+    #[test]
+    fn only_the_most_recent_tracks_are_remembered() {
+        assert_eq!(memory_of(MAX_TRACKS).seen("0", 0), Some(60_000));
+        let mut memory = memory_of(MAX_TRACKS + 1);
+        assert_eq!(memory.seen("0", 0), None);
+        assert_eq!(memory.seen("2", 0), Some(60_000));
+    }
+
+    #[test]
+    fn a_hint_needs_the_track_ten_seconds_behind() {
+        let cases = [(50_001, None), (50_000, Some(60_000)), (0, Some(60_000)), (70_000, None)];
+        for (ms, expected) in cases {
+            let mut memory = memory_of(2);
+            assert_eq!(memory.seen("0", ms), expected, "back at {}", ms);
+        }
+    }
+
+    #[test]
+    fn the_hint_stays_up_for_ten_seconds() {
+        let mut memory = memory_of(2);
+        assert_eq!(memory.seen("0", 0), Some(60_000));
+        assert_eq!(memory.seen("0", 5_000), Some(60_000));
+        let shown = Instant::now().checked_sub(HINT_DURATION).unwrap();
+        memory.hint = memory.hint.map(|(at, _)| (at, shown));
+        assert_eq!(memory.seen("0", 10_000), None);
+
+        // Only coming back to the track brings the hint back, from where it got to since.
+        assert_eq!(memory.seen("1", 0), Some(60_000));
+        assert_eq!(memory.seen("0", 0), Some(10_000));
+    }
+}
@@ -0,0 +1,93 @@
+//! `[schedule]`: players to prefer at certain times of day, such as a podcast app in the
+//! morning and a music player at night.
+//!
+//! Each entry maps a local time range written as `"HH:MM-HH:MM"` to parts of bus names, matched
+//! like `player_priority`. A range ending before it starts runs past midnight. The local time
+//! comes from the C library's `localtime_r`, as the standard library has no notion of time
+//! zones, so schedules only apply on Unix.
+
+use std::collections::BTreeMap;
+
+/// The players preferred right now, from the entry covering the current local time that
+/// starts earliest.
+pub fn active(schedule: &BTreeMap<String, Vec<String>>) -> Option<&[String]> {
+    let now = local_minutes()?;
+    schedule
+        .iter()
+        .filter_map(|(range, players)| Some((parse_range(range)?, players)))
+        .filter(|(range, _)| covers(*range, now))
+        .min_by_key(|((start, _), _)| *start)
+        .map(|(_, players)| players.as_slice())
+}
+
+/// Parses `"06:00-12:30"` into minutes since midnight at either end.
+pub fn parse_range(range: &str) -> Option<(u32, u32)> {
+    let (start, end) = range.split_once('-')?;
+    Some((parse_time(start)?, parse_time(end)?))
+}
+
+fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+/// Whether `now` falls in `[start, end)`, wrapping past midnight when `end` comes first.
+fn covers((start, end): (u32, u32), now: u32) -> bool {
+    if start <= end {
+        (start..end).contains(&now)
+    } else {
+        now >= start || now < end
+    }
+}
+
+/// Minutes since local midnight.
+#[cfg(unix)]
+fn local_minutes() -> Option<u32> {
+    // Safety: a null pointer asks `time` to only return the time, and `tm` is only read after
+    // `localtime_r` has filled it in.
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm = std::mem::MaybeUninit::<libc::tm>::uninit();
+        if libc::localtime_r(&now, tm.as_mut_ptr()).is_null() {
+            return None;
+        }
+        tm.assume_init()
+    };
+    u32::try_from(tm.tm_hour * 60 + tm.tm_min).ok()
+}
+
+#[cfg(not(unix))]
+fn local_minutes() -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_parse_to_minutes() {
+        assert_eq!(parse_range("06:00-12:30"), Some((360, 750)));
+        assert_eq!(parse_range(" 23:59 - 00:00 "), Some((1439, 0)));
+        assert_eq!(parse_range("24:00-01:00"), None);
+        assert_eq!(parse_range("06:60-07:00"), None);
+        assert_eq!(parse_range("06:00"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_time_is_within_a_day() {
+        assert!(local_minutes().is_some_and(|minutes| minutes < 24 * 60));
+    }
+
+    #[test]
+    fn ranges_may_run_past_midnight() {
+        assert!(covers((360, 720), 360));
+        assert!(!covers((360, 720), 720));
+        assert!(covers((1200, 120), 1439));
+        assert!(covers((1200, 120), 0));
+        assert!(!covers((1200, 120), 600));
+    }
+}
@@ -0,0 +1,202 @@
+//! `scrobble_log`: appends every track played long enough to count to a local log in the
+//! Audioscrobbler `.scrobbler.log` format (as written by Rockbox), which scrobbling tools and
+//! Last.fm importers can read back later.
+//!
+//! A play counts once the track has been playing for half its length or four minutes,
+//! whichever comes first, like Last.fm's own rule. Tracks under 30 seconds never count. Time
+//! spent paused doesn't count, and pausing and resuming the same track continues the same play,
+//! so it is logged at most once.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const MIN_LENGTH: Duration = Duration::from_secs(30);
+const MAX_THRESHOLD: Duration = Duration::from_secs(4 * 60);
+
+/// What gets logged about a track.
+pub struct Track {
+    /// `mpris:trackid` when the player has one, otherwise something built from the metadata,
+    /// to tell a new track from the same one resumed.
+    pub id: String,
+    pub artist: String,
+    pub album: String,
+    pub title: String,
+    pub number: String,
+    pub length: Option<Duration>,
+}
+
+/// The track being played and how long it has been heard for.
+struct Play {
+    track: Track,
+    /// When it started, for the log.
+    started: SystemTime,
+    played: Duration,
+    /// Set while it is playing.
+    resumed: Option<Instant>,
+    logged: bool,
+}
+
+pub struct Scrobbler {
+    path: PathBuf,
+    play: Option<Play>,
+}
+
+impl Scrobbler {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, play: None }
+    }
+
+    /// Records that `track` is playing, logging it once it has played long enough.
+    pub fn playing(&mut self, track: Track) {
+        let now = Instant::now();
+        match &mut self.play {
+            Some(play) if play.track.id == track.id => {
+                play.resumed.get_or_insert(now);
+            }
+            _ => {
+                self.play = Some(Play {
+                    track,
+                    started: SystemTime::now(),
+                    played: Duration::ZERO,
+                    resumed: Some(now),
+                    logged: false,
+                });
+            }
+        }
+        let Some(play) = &mut self.play else {
+            return;
+        };
+        let played = play.played + play.resumed.map_or(Duration::ZERO, |at| at.elapsed());
+        if !play.logged && counts(&play.track, played) {
+            play.logged = true;
+            if let Err(e) = append(&self.path, play) {
+                eprintln!("Failed to write to {}: {}", self.path.display(), e);
+            }
+        }
+    }
+
+    /// Records that nothing is playing, e.g. because the player was paused.
+    pub fn stopped(&mut self) {
+        if let Some(play) = &mut self.play {
+            if let Some(at) = play.resumed.take() {
+                play.played += at.elapsed();
+            }
+        }
+    }
+}
+
+fn counts(track: &Track, played: Duration) -> bool {
+    match track.length {
+        Some(length) if length < MIN_LENGTH => false,
+        Some(length) => played >= (length / 2).min(MAX_THRESHOLD),
+        None => played >= MAX_THRESHOLD,
+    }
+}
+
+/// Appends `play` to the log, starting it with the format's header if it is new.
+fn append(path: &PathBuf, play: &Play) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "#AUDIOSCROBBLER/1.1")?;
+        writeln!(file, "#TZ/UTC")?;
+        writeln!(file, "#CLIENT/dbus-now-playing {}", env!("CARGO_PKG_VERSION"))?;
+    }
+    let track = &play.track;
+    let timestamp = play
+        .started
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    // artist, album, title, track number, length in seconds, L(istened), timestamp, MBID.
+    writeln!(
+        file,
+        "{}\t{}\t{}\t{}\t{}\tL\t{}\t",
+        field(&track.artist),
+        field(&track.album),
+        field(&track.title),
+        field(&track.number),
+        track.length.map_or(0, |length| length.as_secs()),
+        timestamp
+    )
+}
+
+/// Tabs and line breaks would break the line apart.
+fn field(text: &str) -> String {
+    text.replace(['\t', '\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: &str, length: Option<u64>) -> Track {
+        Track {
+            id: id.to_string(),
+            artist: "Artist".to_string(),
+            album: "Album".to_string(),
+            title: format!("Title\t{}", id),
+            number: "1".to_string(),
+            length: length.map(Duration::from_secs),
+        }
+    }
+
+    #[test]
+    fn plays_count_at_half_the_length_or_four_minutes() {
+        let cases = [
+            (Some(29), 29, false),
+            (Some(30), 14, false),
+            (Some(30), 15, true),
+            (Some(200), 99, false),
+            (Some(200), 100, true),
+            (Some(480), 239, false),
+            (Some(480), 240, true),
+            (Some(3600), 240, true),
+            (None, 239, false),
+            (None, 240, true),
+        ];
+        for (length, played, expected) in cases {
+            let counted = counts(&track("t", length), Duration::from_secs(played));
+            assert_eq!(counted, expected, "{:?}s long, {}s played", length, played);
+        }
+    }
+
+    #[test]
+    fn a_paused_then_resumed_track_is_logged_once() {
+        let path = std::env::temp_dir().join(format!("scrobble-test-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut scrobbler = Scrobbler::new(path.clone());
+        let played = |scrobbler: &mut Scrobbler, secs| {
+            scrobbler.play.as_mut().unwrap().played = Duration::from_secs(secs);
+        };
+
+        scrobbler.playing(track("a", Some(60)));
+        scrobbler.stopped();
+        scrobbler.stopped();
+        assert!(scrobbler.play.as_ref().unwrap().resumed.is_none());
+        played(&mut scrobbler, 29);
+        // Resuming carries on with the same play rather than starting over.
+        scrobbler.playing(track("a", Some(60)));
+        assert!(!path.exists());
+        played(&mut scrobbler, 30);
+        scrobbler.playing(track("a", Some(60)));
+        scrobbler.stopped();
+        scrobbler.playing(track("a", Some(60)));
+
+        // Another track starts a play of its own.
+        scrobbler.playing(track("b", Some(60)));
+        assert_eq!(scrobbler.play.as_ref().unwrap().played, Duration::ZERO);
+
+        let log = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", log);
+        assert_eq!(lines[0], "#AUDIOSCROBBLER/1.1");
+        assert!(lines[3].starts_with("Artist\tAlbum\tTitle a\t1\t60\tL\t"), "{}", lines[3]);
+    }
+}
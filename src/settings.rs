@@ -0,0 +1,232 @@
+//! The settings window opened by the `settings` click action. It edits the most common
+//! options and saves them back to the config file.
+//!
+//! Only the edited keys are written, in place in the base table of the file, so profiles, other
+//! settings, comments and key order all survive.
+
+use eframe::egui::{
+    self,
+    color_picker::{color_edit_button_srgba, Alpha},
+    Color32, Context,
+};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, Sender},
+};
+use toml_edit::{value, DocumentMut};
+
+use crate::{worker::WorkerEvent, write_atomic, Config, ConfigBuilder};
+
+/// The settings as last saved, for the main window to apply straight away.
+pub struct Saved {
+    pub fg_color: Color32,
+    pub bg_color: Color32,
+    pub artist_color: Color32,
+    pub position: (i32, i32),
+    pub player: Option<String>,
+}
+
+pub struct Settings {
+    path: PathBuf,
+    fg_color: Color32,
+    bg_color: Color32,
+    artist_color: Color32,
+    window_x: i32,
+    window_y: i32,
+    /// `dbus_service`; `None` leaves the choice to discovery.
+    player: Option<String>,
+    /// Bus names and identities of the players offered in the picker.
+    players: Vec<(String, String)>,
+    /// Where the worker's list of players arrives, until it has.
+    listing: Option<Receiver<Vec<(String, String)>>>,
+    /// Result of the last save.
+    message: Option<String>,
+}
+
+impl Settings {
+    /// Opens the window, asking `worker` for the players to offer; until they arrive only the
+    /// configured `dbus_service` is.
+    pub fn new(
+        path: PathBuf,
+        config: &Config,
+        artist_color: Color32,
+        worker: &Sender<WorkerEvent>,
+    ) -> Self {
+        let (reply, listing) = mpsc::channel();
+        let listing = worker.send(WorkerEvent::ListPlayers(reply)).is_ok().then_some(listing);
+        let mut settings = Self {
+            path,
+            fg_color: config.fg(),
+            bg_color: config.bg(),
+            artist_color,
+            window_x: config.window_x.unwrap_or(0),
+            window_y: config.window_y.unwrap_or(1000),
+            player: config.dbus_service.clone(),
+            players: Vec::new(),
+            listing,
+            message: None,
+        };
+        settings.set_players(Vec::new());
+        settings
+    }
+
+    /// Offers `players`, along with the chosen one even if it isn't on the bus right now.
+    fn set_players(&mut self, mut players: Vec<(String, String)>) {
+        if let Some(service) = &self.player {
+            if !players.iter().any(|(name, _)| name == service) {
+                players.push((service.clone(), service.clone()));
+            }
+        }
+        self.players = players;
+    }
+
+    /// Shows the window for this frame. Returns whether it should stay open, and the new
+    /// settings when they were just saved.
+    pub fn show(&mut self, ctx: &Context) -> (bool, Option<Saved>) {
+        if let Some(players) = self.listing.as_ref().and_then(|listing| listing.try_recv().ok()) {
+            self.listing = None;
+            self.set_players(players);
+        }
+        let viewport = egui::ViewportBuilder::default()
+            .with_title("Now Playing settings")
+            .with_inner_size([340.0, 220.0])
+            .with_resizable(false);
+        ctx.show_viewport_immediate(
+            egui::ViewportId::from_hash_of("settings"),
+            viewport,
+            |ctx, _class| {
+                let mut open = !ctx.input(|i| i.viewport().close_requested());
+                let mut saved = None;
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    egui::Grid::new("settings_grid")
+                        .num_columns(2)
+                        .spacing([12.0, 8.0])
+                        .show(ui, |ui| self.fields(ui));
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            saved = self.save_and_report();
+                        }
+                        if ui.button("Close").clicked() {
+                            open = false;
+                        }
+                    });
+                    if let Some(message) = &self.message {
+                        ui.label(message);
+                    }
+                });
+                (open, saved)
+            },
+        )
+    }
+
+    fn fields(&mut self, ui: &mut egui::Ui) {
+        ui.label("Text color");
+        color_edit_button_srgba(ui, &mut self.fg_color, Alpha::OnlyBlend);
+        ui.end_row();
+
+        ui.label("Background");
+        color_edit_button_srgba(ui, &mut self.bg_color, Alpha::OnlyBlend);
+        ui.end_row();
+
+        ui.label("Artist color");
+        color_edit_button_srgba(ui, &mut self.artist_color, Alpha::OnlyBlend);
+        ui.end_row();
+
+        ui.label("Position");
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut self.window_x).prefix("x "));
+            ui.add(egui::DragValue::new(&mut self.window_y).prefix("y "));
+        });
+        ui.end_row();
+
+        ui.label("Player");
+        let selected = match &self.player {
+            None => "Automatic".to_string(),
+            Some(service) => self
+                .players
+                .iter()
+                .find(|(name, _)| name == service)
+                .map_or_else(|| service.clone(), |(_, identity)| identity.clone()),
+        };
+        egui::ComboBox::from_id_salt("player")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.player, None, "Automatic");
+                for (name, identity) in &self.players {
+                    ui.selectable_value(&mut self.player, Some(name.clone()), identity);
+                }
+            });
+        ui.end_row();
+    }
+
+    /// Saves, leaving the outcome in `message`.
+    fn save_and_report(&mut self) -> Option<Saved> {
+        match self.save() {
+            Ok(()) => {
+                self.message = Some(format!("Saved to {}", self.path.display()));
+                Some(self.saved())
+            }
+            Err(e) => {
+                self.message = Some(e);
+                None
+            }
+        }
+    }
+
+    fn saved(&self) -> Saved {
+        Saved {
+            fg_color: self.fg_color,
+            bg_color: self.bg_color,
+            artist_color: self.artist_color,
+            position: (self.window_x, self.window_y),
+            player: self.player.clone(),
+        }
+    }
+
+    /// Writes the edited keys into the config file, refusing if the result wouldn't validate.
+    fn save(&self) -> Result<(), String> {
+        let mut doc: DocumentMut = match fs::read_to_string(&self.path) {
+            Ok(content) => content
+                .parse()
+                .map_err(|e| format!("Not saving, {} is invalid: {}", self.path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => DocumentMut::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.path.display(), e)),
+        };
+        doc["fg_color"] = value(color_hex(self.fg_color));
+        doc["bg_color"] = value(color_hex(self.bg_color));
+        doc["artist_color"] = value(color_hex(self.artist_color));
+        doc["window_x"] = value(i64::from(self.window_x));
+        doc["window_y"] = value(i64::from(self.window_y));
+        match &self.player {
+            Some(service) => doc["dbus_service"] = value(service.as_str()),
+            None => {
+                doc.remove("dbus_service");
+            }
+        }
+
+        let content = doc.to_string();
+        let mut base: toml::Table = toml::from_str(&content).map_err(|e| e.to_string())?;
+        base.remove("profiles");
+        let mut builder = ConfigBuilder::new();
+        builder.overrides(base);
+        let errors = builder.build()?.validate();
+        if !errors.is_empty() {
+            return Err(format!("Not saving: {}", errors.join("; ")));
+        }
+
+        write_atomic(&self.path, &content)
+            .map_err(|e| format!("Failed to save {}: {}", self.path.display(), e))
+    }
+}
+
+/// Formats `color` the way the config expects it, with alpha only when translucent.
+pub fn color_hex(color: Color32) -> String {
+    let [r, g, b, a] = color.to_srgba_unmultiplied();
+    if a == 255 {
+        format!("#{:02X}{:02X}{:02X}", r, g, b)
+    } else {
+        format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a)
+    }
+}
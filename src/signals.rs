@@ -0,0 +1,107 @@
+//! Unix signals as a lightweight remote control for shell scripts: `SIGUSR1` polls the player
+//! right away and redraws, and `SIGUSR2` hides the window or shows it again, e.g.
+//! `pkill -USR2 -f dbus-now-playing`.
+//!
+//! Little is safe inside a signal handler, so the handlers only write a byte naming the signal
+//! to a pipe. A thread blocked on the other end passes it on to the worker and the window.
+
+use std::{
+    fs::File,
+    io::Read,
+    os::fd::FromRawFd,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        mpsc::Sender,
+    },
+    thread,
+};
+
+use eframe::egui::{Context, ViewportCommand};
+
+use crate::worker::WorkerEvent;
+
+const REFRESH: u8 = b'r';
+const TOGGLE: u8 = b't';
+
+/// The pipe's write end, or -1 while there is none.
+static WAKE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_signal(signum: libc::c_int) {
+    let byte = match signum {
+        libc::SIGUSR1 => REFRESH,
+        libc::SIGUSR2 => TOGGLE,
+        _ => return,
+    };
+    let fd = WAKE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        // Safety: `write` is async-signal-safe, and the byte outlives the call. A full pipe
+        // already holds a wake-up, so a failed write loses nothing.
+        unsafe {
+            libc::write(fd, [byte].as_ptr().cast(), 1);
+        }
+    }
+}
+
+/// The read end of the pipe the handlers write to.
+pub struct Signals {
+    pipe: File,
+}
+
+/// Installs the handlers. Until then either signal ends the process, so this comes before
+/// anything that runs for long. Signals that come in before [`Signals::listen`] wait in the
+/// pipe.
+pub fn install() -> Option<Signals> {
+    let mut fds = [-1; 2];
+    // Safety: `fds` has room for the two descriptors `pipe` writes.
+    let piped = unsafe { libc::pipe(fds.as_mut_ptr()) } == 0;
+    if piped {
+        // Safety: both descriptors were just opened. Players and scripts started later
+        // shouldn't inherit them, and the handler must never block on a full pipe.
+        unsafe {
+            for fd in fds {
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+            libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK);
+        }
+        WAKE.store(fds[1], Ordering::Relaxed);
+    } else {
+        eprintln!("Failed to set up SIGUSR1/SIGUSR2: {}", std::io::Error::last_os_error());
+    }
+    for signum in [libc::SIGUSR1, libc::SIGUSR2] {
+        // Safety: `on_signal` only makes async-signal-safe calls.
+        unsafe {
+            libc::signal(signum, on_signal as *const () as libc::sighandler_t);
+        }
+    }
+    // Safety: `pipe` opened `fds[0]` and nothing else owns it.
+    piped.then(|| Signals {
+        pipe: unsafe { File::from_raw_fd(fds[0]) },
+    })
+}
+
+impl Signals {
+    /// Acts on signals from now on: `SIGUSR1` has `worker` poll, and both redraw the window
+    /// `ctx` belongs to, if there is one.
+    pub fn listen(mut self, worker: Sender<WorkerEvent>, ctx: Option<Context>) {
+        thread::spawn(move || {
+            let mut hidden = false;
+            let mut byte = [0];
+            while self.pipe.read_exact(&mut byte).is_ok() {
+                match byte[0] {
+                    // The worker only goes away when the app quits.
+                    REFRESH if worker.send(WorkerEvent::Refresh).is_err() => return,
+                    TOGGLE => {
+                        if let Some(ctx) = &ctx {
+                            hidden = !hidden;
+                            ctx.send_viewport_cmd(ViewportCommand::Visible(!hidden));
+                        }
+                    }
+                    _ => {}
+                }
+                if let Some(ctx) = &ctx {
+                    ctx.request_repaint();
+                }
+            }
+        });
+    }
+}
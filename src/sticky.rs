@@ -0,0 +1,77 @@
+//! `remember_last_track`: keeps the last shown track in a small state file, so a restarted
+//! widget can show it straight away instead of the idle text until the first poll.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{expand_path, NowPlaying, METADATA_TOKENS};
+
+/// `$XDG_STATE_HOME/dbus-now-playing/last_track.toml`, per the XDG base directory spec.
+pub fn default_path() -> PathBuf {
+    match std::env::var("XDG_STATE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => expand_path("~/.local/state"),
+    }
+    .join("dbus-now-playing")
+    .join("last_track.toml")
+}
+
+/// Reads the remembered track. Missing or unreadable files just mean there is none.
+pub fn load(path: &Path) -> Option<NowPlaying> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut table: toml::Table = toml::from_str(&content).ok()?;
+    let mut take = |key: &str| match table.remove(key) {
+        Some(toml::Value::String(s)) => s,
+        _ => String::new(),
+    };
+    let title = take("title");
+    let artist = take("artist");
+    let player = take("player");
+    if title.is_empty() {
+        return None;
+    }
+    let fields = match table.remove("fields") {
+        Some(toml::Value::Table(fields)) => fields
+            .into_iter()
+            .filter_map(|(name, value)| Some((token(&name)?, value.as_str()?.to_string())))
+            .collect(),
+        _ => Default::default(),
+    };
+    Some(NowPlaying {
+        title,
+        artist,
+        player,
+        fields,
+        position: None,
+        lyrics: None,
+        art_url: None,
+        playing: false,
+        left_off_at: None,
+    })
+}
+
+/// The state file contents for `current`.
+pub fn serialize(current: &NowPlaying) -> String {
+    let mut table = toml::Table::new();
+    table.insert("title".to_string(), current.title.clone().into());
+    table.insert("artist".to_string(), current.artist.clone().into());
+    table.insert("player".to_string(), current.player.clone().into());
+    let fields: toml::Table = current
+        .fields
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.clone().into()))
+        .collect();
+    table.insert("fields".to_string(), fields.into());
+    toml::to_string(&table).unwrap_or_default()
+}
+
+/// Maps a stored field name back onto the token it was saved from.
+pub fn token(name: &str) -> Option<&'static str> {
+    METADATA_TOKENS
+        .iter()
+        .map(|(token, _)| *token)
+        .chain(["station", "rating", "queue_pos", "queue_len"])
+        .find(|token| *token == name)
+}
@@ -0,0 +1,179 @@
+//! `--tui`: shows the now-playing line in the terminal instead of a window, redrawn in place,
+//! for SSH sessions and machines without a display.
+//!
+//! The line is rendered from the same `format` as the window, colors included (as 24-bit ANSI
+//! colors, unless stdout isn't a terminal or `NO_COLOR` is set), and cut short with the
+//! `ellipsis` to fit the terminal width. Widths are counted in terminal columns, so wide
+//! characters such as CJK and most emoji take two.
+
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use eframe::egui::Color32;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::{AppState, Segment, Template, TextTransform};
+
+/// How often the line is re-rendered, so the time and position tokens keep moving, and the
+/// terminal width looked up again, to follow resizes.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Shows the current track until stdout is closed.
+pub fn run(
+    shared: Arc<Mutex<AppState>>,
+    template: &Template,
+    text_transform: TextTransform,
+    idle_text: &str,
+    error_text: &str,
+    error_color: Color32,
+) {
+    let mut stdout = io::stdout();
+    let colored = stdout.is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let mut last = None;
+    loop {
+        let segments = {
+            let state = shared.lock().unwrap();
+            match (&state.current, &state.bus_error) {
+                (Some(current), _) => {
+                    let mut segments = template.render(current);
+                    for segment in &mut segments {
+                        segment.text = text_transform.apply(&segment.text);
+                    }
+                    segments
+                }
+                (None, Some(_)) => vec![plain(error_text, Some(error_color))],
+                (None, None) => vec![plain(idle_text, None)],
+            }
+        };
+        // Leave the last column free, as some terminals wrap as soon as it is written.
+        let line = fit(segments, terminal_width().saturating_sub(1), &template.ellipsis);
+        let line = if colored {
+            line.iter().map(ansi).collect()
+        } else {
+            line.into_iter().map(|segment| segment.text).collect::<String>()
+        };
+        if last.as_ref() != Some(&line) {
+            // Back to the start of the line and clear it, then draw the new one.
+            let written = write!(stdout, "\r\x1b[2K{}", line).and_then(|_| stdout.flush());
+            if written.is_err() {
+                return;
+            }
+            last = Some(line);
+        }
+        thread::sleep(REDRAW_INTERVAL);
+    }
+}
+
+fn plain(text: &str, color: Option<Color32>) -> Segment {
+    Segment {
+        text: text.to_string(),
+        color,
+        time: false,
+    }
+}
+
+/// Cuts `segments` short to at most `width` columns, ending in `ellipsis` if anything was cut.
+/// Line breaks become spaces, as the line is redrawn in place.
+fn fit(segments: Vec<Segment>, width: usize, ellipsis: &str) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = segments
+        .into_iter()
+        .map(|segment| Segment {
+            text: segment.text.replace(['\n', '\r', '\t'], " "),
+            ..segment
+        })
+        .collect();
+    let total: usize = segments.iter().map(|s| s.text.width()).sum();
+    if total <= width {
+        return segments;
+    }
+    let mut budget = width.saturating_sub(ellipsis.width());
+    for segment in &mut segments {
+        let mut used = 0;
+        let cut = segment.text.char_indices().find(|(_, c)| {
+            used += c.width().unwrap_or(0);
+            used > budget
+        });
+        match cut {
+            Some((at, _)) => {
+                segment.text.truncate(at);
+                budget = 0;
+            }
+            None => budget -= used,
+        }
+    }
+    segments.retain(|segment| !segment.text.is_empty());
+    match segments.last_mut() {
+        Some(last) => last.text.push_str(ellipsis),
+        None => segments.push(plain(ellipsis, None)),
+    }
+    segments
+}
+
+/// `segment` in its color, or the terminal's own for uncolored text.
+fn ansi(segment: &Segment) -> String {
+    match segment.color {
+        Some(color) => format!(
+            "\x1b[38;2;{};{};{}m{}\x1b[0m",
+            color.r(),
+            color.g(),
+            color.b(),
+            segment.text
+        ),
+        None => segment.text.clone(),
+    }
+}
+
+/// The terminal's width in columns: what the terminal on stdout reports, or else `$COLUMNS`,
+/// or else 80.
+fn terminal_width() -> usize {
+    #[cfg(unix)]
+    {
+        let mut size = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+        // Safety: `TIOCGWINSZ` only writes a `winsize` through the pointer.
+        let read = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if read == 0 && size.ws_col > 0 {
+            return usize::from(size.ws_col);
+        }
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|columns| *columns > 0)
+        .unwrap_or(80)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(segments: Vec<Segment>) -> Vec<String> {
+        segments.into_iter().map(|segment| segment.text).collect()
+    }
+
+    #[test]
+    fn lines_are_cut_to_the_width_in_columns() {
+        let cases: [(&[&str], usize, &[&str]); 6] = [
+            (&["Song", " - ", "Artist"], 13, &["Song", " - ", "Artist"]),
+            (&["Song", " - ", "Artist"], 10, &["Song", " - ", "Ar…"]),
+            (&["Song", " - ", "Artist"], 5, &["Song…"]),
+            // Wide characters take two columns, and are never cut in half.
+            (&["日本語の歌"], 10, &["日本語の歌"]),
+            (&["日本語の歌"], 6, &["日本…"]),
+            (&["🎵 Song"], 4, &["🎵 …"]),
+        ];
+        for (segments, width, expected) in cases {
+            let segments = segments.iter().map(|text| plain(text, None)).collect();
+            assert_eq!(texts(fit(segments, width, "…")), expected, "{:?} in {}", expected, width);
+        }
+    }
+
+    #[test]
+    fn line_breaks_become_spaces() {
+        let segments = vec![plain("Line\none\ttab", None)];
+        assert_eq!(texts(fit(segments, 80, "…")), ["Line one tab"]);
+    }
+}
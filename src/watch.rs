@@ -0,0 +1,138 @@
+//! Headless modes that print every change to the current track to stdout instead of showing
+//! a window.
+//!
+//! `--watch` prints one JSON object per line (NDJSON), for feeding into other programs. Each
+//! event carries a `seq` that increases by one per line and a `timestamp` in milliseconds since
+//! the Unix epoch, so consumers can spot gaps and order events. Where the player reports them,
+//! `length_ms` and `position_ms` say how long the track is and how far in it was at the event;
+//! the position moving along on its own doesn't make an event.
+//!
+//! `--follow` prints the `format` template as plain text, like `playerctl metadata --follow`.
+
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{AppState, Position, Template};
+
+/// How often the shared state is checked for changes.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `--watch`: prints an event for the initial state and then one per change. Returns once
+/// stdout is closed.
+pub fn run(shared: Arc<Mutex<AppState>>) {
+    let mut stdout = io::stdout();
+    let mut seq: u64 = 0;
+    on_change(&shared, body, |body| {
+        seq += 1;
+        let position = position_ms(&shared.lock().unwrap());
+        print_line(&mut stdout, &event(seq, body, position))
+    });
+}
+
+/// `--follow`: prints `template` for the current track whenever the output changes, or an
+/// empty line while nothing is playing. Returns once stdout is closed.
+pub fn follow(shared: Arc<Mutex<AppState>>, template: &Template) {
+    let render = |state: &AppState| {
+        state.current.as_ref().map_or_else(String::new, |current| {
+            template.render(current).into_iter().map(|s| s.text).collect()
+        })
+    };
+    let mut stdout = io::stdout();
+    on_change(&shared, render, |text| print_line(&mut stdout, text));
+}
+
+/// Calls `emit` with `describe(state)` for the initial state and again each time `describe`
+/// gives something new, until `emit` returns `false`.
+pub fn on_change(
+    shared: &Mutex<AppState>,
+    describe: impl Fn(&AppState) -> String,
+    mut emit: impl FnMut(&str) -> bool,
+) {
+    let mut last: Option<String> = None;
+    loop {
+        let description = describe(&shared.lock().unwrap());
+        if last.as_deref() != Some(description.as_str()) {
+            if !emit(&description) {
+                return;
+            }
+            last = Some(description);
+        }
+        thread::sleep(CHECK_INTERVAL);
+    }
+}
+
+/// Writes `line` to stdout, returning `false` once stdout is closed.
+fn print_line(stdout: &mut io::Stdout, line: &str) -> bool {
+    writeln!(stdout, "{}", line).and_then(|_| stdout.flush()).is_ok()
+}
+
+/// A complete `--watch` event: `body` with its sequence number, the current time and the
+/// position at that time, if known.
+pub fn event(seq: u64, body: &str, position_ms: Option<i64>) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let position = position_ms.map_or_else(String::new, |ms| format!(",\"position_ms\":{}", ms));
+    format!("{{\"seq\":{},\"timestamp\":{},{}{}}}", seq, timestamp, body, position)
+}
+
+/// How far into the current track playback is, in milliseconds. Kept out of [`body`], as it
+/// changes all the time.
+pub fn position_ms(state: &AppState) -> Option<i64> {
+    state.current.as_ref()?.position.as_ref().map(Position::now_ms)
+}
+
+/// The event fields describing `state`, without the surrounding braces.
+pub fn body(state: &AppState) -> String {
+    const NO_TRACK: &str = "\"player\":null,\"title\":null,\"artist\":null,\"fields\":{}";
+    if let Some(error) = &state.bus_error {
+        return format!("\"status\":\"Error\",\"error\":{},{}", quote(error), NO_TRACK);
+    }
+    let Some(now) = &state.current else {
+        return format!("\"status\":\"Idle\",{}", NO_TRACK);
+    };
+    let mut fields: Vec<_> = now.fields.iter().collect();
+    fields.sort();
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|(name, value)| format!("{}:{}", quote(name), quote(value)))
+        .collect();
+    let status = if now.playing { "Playing" } else { "Paused" };
+    let length = now
+        .position
+        .as_ref()
+        .and_then(|position| position.length)
+        .map_or_else(String::new, |micros| format!(",\"length_ms\":{}", micros / 1000));
+    format!(
+        "\"status\":\"{}\",\"player\":{},\"title\":{},\"artist\":{},\"fields\":{{{}}}{}",
+        status,
+        quote(&now.player),
+        quote(&now.title),
+        quote(&now.artist),
+        fields.join(","),
+        length
+    )
+}
+
+/// Encodes `s` as a JSON string literal.
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,1595 @@
+//! The D-Bus side of the app: follows a player and publishes its track into [`AppState`].
+//!
+//! The worker is a single event loop over a channel. Signals from the bus and commands from the
+//! UI arrive as [`WorkerEvent`]s; when nothing arrives within the poll interval the
+//! followed player is polled anyway, so players that never emit signals still work.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use zbus::{
+    blocking::{Connection, MessageIterator, Proxy},
+    fdo,
+    message::Type,
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+    MatchRule,
+};
+
+use crate::{
+    art::ArtWriter,
+    fallback::Fallback,
+    collapse_whitespace, discover_player, expand_path, extract_artist_metadata,
+    extract_metadata_fields, extract_string_metadata, find_matching_player, list_players,
+    mapped_key, player_identity,
+    resume::ResumeMemory,
+    scrobble::{Scrobbler, Track},
+    sticky, value_to_text, write_atomic, Action, AppState, Config, DbusNames, IdleInhibitor,
+    NowPlaying, Position,
+};
+
+/// How often the followed player is polled when it doesn't emit any signals.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often discovery re-runs while no player is found.
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(2);
+/// Pause between attempts when a property read fails transiently.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+/// How much `volume_up` / `volume_down` change the player's `Volume`.
+const VOLUME_STEP: f64 = 0.05;
+
+pub enum WorkerEvent {
+    /// A player's `PropertiesChanged` signal (or the `[dbus]` signal); `sender` is its unique
+    /// bus name. `changes` is `None` when the signal doesn't say what changed.
+    PropertiesChanged {
+        sender: String,
+        changes: Option<PropertyChanges>,
+    },
+    /// A player's `Seeked` signal: its position jumped.
+    Seeked { sender: String },
+    /// A player action triggered from the UI.
+    Command(Action),
+    /// A new `dbus_service` chosen in the settings window; `None` goes back to discovery.
+    SetPlayer(Option<String>),
+    /// The window reappeared; poll right away rather than at the next interval.
+    Refresh,
+    /// The progress bar's knob was dragged to this fraction of the track.
+    Seek(f32),
+    /// A player's bus name changed hands: `old_owner` is empty when the player just started,
+    /// `new_owner` when it quit, and neither when another process took the name over.
+    NameOwnerChanged { old_owner: String, new_owner: String },
+    /// The settings window wants the players on the bus, as bus names with their identities,
+    /// so it never blocks on D-Bus itself.
+    ListPlayers(Sender<Vec<(String, String)>>),
+}
+
+/// The body of a `PropertiesChanged` signal.
+pub struct PropertyChanges {
+    interface: String,
+    changed: HashMap<String, OwnedValue>,
+    invalidated: Vec<String>,
+}
+
+impl PropertyChanges {
+    /// Whether these say an MPRIS player started playing.
+    fn started_playing(&self) -> bool {
+        self.interface == "org.mpris.MediaPlayer2.Player"
+            && self
+                .changed
+                .get("PlaybackStatus")
+                .is_some_and(|status| is_playing(status))
+    }
+}
+
+/// What a followed player's change calls for.
+enum Reaction {
+    /// Nothing shown depends on it (volume, shuffle, ...).
+    Ignore,
+    /// The player paused or stopped: it can be let go without reading anything. A paused
+    /// player's track stays shown, as paused.
+    Stopped { paused: bool },
+    /// The signal carried the track's new metadata, so it can be shown without reading it
+    /// back from the player.
+    Track(HashMap<String, Value<'static>>),
+    /// Only the playback rate changed, which only changes the progress bar's pace.
+    Rate(f64),
+    /// Something shown changed without the signal saying what to; poll the player.
+    Poll,
+}
+
+pub struct Worker {
+    shared: Arc<Mutex<AppState>>,
+    config: Config,
+    /// `--player`: follow a player whose bus name contains this.
+    player_match: Option<String>,
+    inhibitor: Option<Arc<Mutex<IdleInhibitor>>>,
+    /// Set by the `freeze` action: keep showing the current track until it is cleared.
+    frozen: Arc<AtomicBool>,
+    identities: HashMap<String, String>,
+    /// Token -> metadata key overrides from the config.
+    field_map: HashMap<String, String>,
+    /// `[dbus]`: where the followed player's track is read from.
+    dbus: DbusNames,
+    /// Whether the format shows `{position_ms}` or the progress bar is on; `Position` isn't read
+    /// otherwise.
+    read_position: bool,
+    /// Whether the format shows `{queue_pos}` or `{queue_len}`; the track list isn't read
+    /// otherwise.
+    read_queue: bool,
+    followed: Option<String>,
+    /// `remember_last_track`: where to save each new track, and what was saved last.
+    state_file: Option<PathBuf>,
+    saved: Option<String>,
+    scrobbler: Option<Scrobbler>,
+    art: Option<ArtWriter>,
+    /// When each player (by unique bus name) last signalled a change, for
+    /// `multi_playing_strategy = "most_recent"`.
+    activity: HashMap<String, Instant>,
+    /// `show_delay_ms`: how long a new track has to stay current before it is shown, and the
+    /// track waiting out that delay with when it was first seen.
+    show_delay: Duration,
+    pending: Option<(String, Instant)>,
+    resume: Option<ResumeMemory>,
+    /// `fallback_command`, asked for the track while no player is playing.
+    fallback: Option<Fallback>,
+    /// `hide_patterns`, lowercased unless `hide_patterns_case_sensitive` is set.
+    hide_patterns: Vec<String>,
+    hide_case_sensitive: bool,
+    /// After `idle_backoff_after_ms` with nothing playing, discovery slows down to
+    /// `idle_backoff_interval_ms`. `None`, the default, keeps it at the usual pace.
+    idle_backoff: Option<(Duration, Duration)>,
+    events_tx: Sender<WorkerEvent>,
+    events_rx: Receiver<WorkerEvent>,
+}
+
+/// Why the worker stopped following a player.
+enum Unfollow {
+    /// The player stopped playing or vanished; run discovery again. `played` says whether it
+    /// played at all while followed, as opposed to being picked while already paused.
+    Rediscover { played: bool },
+    /// A different player was chosen, or the player's bus name changed hands; run discovery
+    /// right away.
+    Switch,
+}
+
+impl Worker {
+    pub fn new(
+        shared: Arc<Mutex<AppState>>,
+        config: Config,
+        player_match: Option<String>,
+        inhibitor: Option<Arc<Mutex<IdleInhibitor>>>,
+        frozen: Arc<AtomicBool>,
+    ) -> Self {
+        let (events_tx, events_rx) = mpsc::channel();
+        let field_map = config.field_map.clone().unwrap_or_default();
+        let dbus = config.dbus.clone().unwrap_or_default();
+        let state_file = config.state_file();
+        let read_position = config.shows_position();
+        let read_queue = config.shows_queue();
+        let show_delay = Duration::from_millis(config.show_delay_ms.unwrap_or(0));
+        let art = config
+            .art_output_path
+            .as_deref()
+            .map(|path| ArtWriter::new(expand_path(path)));
+        let scrobbler = config
+            .scrobble_log
+            .as_deref()
+            .map(|path| Scrobbler::new(expand_path(path)));
+        let fallback = config.fallback_command.clone().map(|command| {
+            let interval = config.fallback_interval_ms.unwrap_or(5000);
+            Fallback::new(command, Duration::from_millis(interval), events_tx.clone())
+        });
+        let hide_case_sensitive = config.hide_patterns_case_sensitive.unwrap_or(false);
+        let hide_patterns = config
+            .hide_patterns
+            .iter()
+            .flatten()
+            .filter(|pattern| !pattern.is_empty())
+            .map(|pattern| {
+                if hide_case_sensitive {
+                    pattern.clone()
+                } else {
+                    pattern.to_lowercase()
+                }
+            })
+            .collect();
+        let idle_backoff = match config.idle_backoff_after_ms.unwrap_or(0) {
+            0 => None,
+            after => {
+                let interval = config.idle_backoff_interval_ms.unwrap_or(30_000);
+                Some((Duration::from_millis(after), Duration::from_millis(interval)))
+            }
+        };
+        let resume = config
+            .resume_hint
+            .unwrap_or(false)
+            .then(ResumeMemory::default);
+        Self {
+            shared,
+            config,
+            player_match,
+            inhibitor,
+            frozen,
+            identities: HashMap::new(),
+            field_map,
+            dbus,
+            read_position,
+            read_queue,
+            followed: None,
+            state_file,
+            saved: None,
+            scrobbler,
+            art,
+            activity: HashMap::new(),
+            show_delay,
+            pending: None,
+            resume,
+            fallback,
+            hide_patterns,
+            hide_case_sensitive,
+            idle_backoff,
+            events_tx,
+            events_rx,
+        }
+    }
+
+    /// Reads `Position` even when nothing shown needs it.
+    pub fn always_read_position(&mut self) {
+        self.read_position = true;
+    }
+
+    pub fn sender(&self) -> Sender<WorkerEvent> {
+        self.events_tx.clone()
+    }
+
+    pub fn run(mut self) {
+        // The last connection error logged. While the bus stays down, retries only log when
+        // the error changes, so a service's log isn't flooded every 5s.
+        let mut logged_error: Option<String> = None;
+        loop {
+            let connection = match Connection::session() {
+                Ok(c) => c,
+                Err(e) => {
+                    let error = e.to_string();
+                    if logged_error.as_ref() != Some(&error) {
+                        eprintln!("Failed to connect to D-Bus: {}. Retrying every 5s...", e);
+                        logged_error = Some(error.clone());
+                    }
+                    let mut shared = self.shared.lock().unwrap();
+                    shared.current = None;
+                    shared.bus_error = Some(error);
+                    drop(shared);
+                    thread::sleep(Duration::from_secs(5));
+                    continue;
+                }
+            };
+            if logged_error.take().is_some() {
+                eprintln!("Connected to D-Bus.");
+            }
+            self.shared.lock().unwrap().bus_error = None;
+            spawn_signal_listener(
+                &connection,
+                self.dbus.object_path(),
+                "org.freedesktop.DBus.Properties",
+                "PropertiesChanged",
+                self.events_tx.clone(),
+            );
+            if let Some((interface, member)) = self.dbus.signal() {
+                spawn_signal_listener(
+                    &connection,
+                    self.dbus.object_path(),
+                    interface,
+                    member,
+                    self.events_tx.clone(),
+                );
+            }
+            spawn_player_watcher(
+                &connection,
+                self.config.dbus_service.as_deref(),
+                self.events_tx.clone(),
+            );
+            if self.read_position {
+                spawn_signal_listener(
+                    &connection,
+                    "/org/mpris/MediaPlayer2",
+                    "org.mpris.MediaPlayer2.Player",
+                    "Seeked",
+                    self.events_tx.clone(),
+                );
+            }
+
+            // --- Main Player Discovery Loop ---
+            // Since when nothing has been playing, whether or not discovery found a player.
+            let mut idle_since: Option<Instant> = None;
+            loop {
+                // Precedence: --player, then dbus_service, then the media_shim while it is on
+                // the bus, then discovery unless strict_player rules it out. Neither of the first
+                // two ever falls back to another player.
+                let shim = self
+                    .config
+                    .media_shim_name()
+                    .filter(|name| name_owner(&connection, name).is_some());
+                let found = if let Some(pattern) = &self.player_match {
+                    find_matching_player(&connection, pattern)
+                } else if let Some(name) = &self.config.dbus_service {
+                    Ok(Some(name.clone()))
+                } else if let Some(name) = shim {
+                    Ok(Some(name.to_string()))
+                } else if self.config.strict_player.unwrap_or(false) {
+                    Ok(None)
+                } else {
+                    let last_active = |name: &str| {
+                        let owner = name_owner(&connection, name)?;
+                        self.activity.get(&owner).copied()
+                    };
+                    discover_player(
+                        &connection,
+                        self.followed.as_deref(),
+                        &self.config,
+                        last_active,
+                    )
+                };
+                let service_name = match found {
+                    Ok(name) => name,
+                    // The bus itself went away; reconnect rather than report an idle system.
+                    Err(zbus::Error::InputOutput(e)) => {
+                        eprintln!("Lost the D-Bus connection: {}. Reconnecting...", e);
+                        break;
+                    }
+                    Err(_) => None,
+                };
+
+                // Pause briefly after a paused fallback pick so it doesn't spin straight back
+                // into discovery, and for longer when no player is found at all.
+                let mut interval = DISCOVERY_INTERVAL;
+                match service_name {
+                    Some(service_name) => match self.follow(&connection, &service_name) {
+                        Unfollow::Rediscover { played } => {
+                            if played {
+                                idle_since = None;
+                            }
+                            interval = Duration::from_millis(500);
+                        }
+                        Unfollow::Switch => {
+                            idle_since = None;
+                            continue;
+                        }
+                    },
+                    None => self.set_idle(),
+                }
+
+                // Nothing is playing now, though the fallback may be showing a track.
+                let showing = self
+                    .shared
+                    .lock()
+                    .unwrap()
+                    .current
+                    .as_ref()
+                    .is_some_and(|now| now.playing);
+                if showing {
+                    idle_since = None;
+                } else if let Some((after, backoff)) = self.idle_backoff {
+                    let since = *idle_since.get_or_insert_with(Instant::now);
+                    if since.elapsed() >= after {
+                        interval = backoff;
+                    }
+                }
+                self.wait(&connection, interval);
+            }
+        }
+    }
+
+    /// Polls `service_name` until it stops playing, re-reading early whenever it signals a change.
+    fn follow(&mut self, connection: &Connection, service_name: &str) -> Unfollow {
+        self.followed = Some(service_name.to_string());
+        let player = self
+            .identities
+            .entry(service_name.to_string())
+            .or_insert_with(|| player_identity(connection, service_name))
+            .clone();
+        let owner = name_owner(connection, service_name);
+
+        let proxy = match Proxy::new(
+            connection,
+            service_name.to_string(),
+            self.dbus.object_path().to_string(),
+            self.dbus.interface().to_string(),
+        ) {
+            Ok(p) => p,
+            // Can't create proxy, player might have just closed.
+            Err(_) => {
+                self.set_idle();
+                return Unfollow::Rediscover { played: false };
+            }
+        };
+
+        let tracklist = self
+            .read_queue
+            .then(|| {
+                Proxy::new(
+                    connection,
+                    service_name.to_string(),
+                    "/org/mpris/MediaPlayer2",
+                    "org.mpris.MediaPlayer2.TrackList",
+                )
+                .ok()
+            })
+            .flatten();
+
+        // Whether the player's status said it is playing, rather than it being assumed.
+        let mut confirmed = true;
+        // Whether the last poll found the player paused, rather than stopped or gone.
+        let mut paused = false;
+        // Metadata that came with the last signal, shown in place of polling.
+        let mut signalled = None;
+
+        // Whether any poll found the player playing.
+        let mut played = false;
+
+        // --- Track Info Polling Loop ---
+        loop {
+            let playing = match signalled.take() {
+                Some(metadata) => self.show(
+                    connection,
+                    &proxy,
+                    tracklist.as_ref(),
+                    &player,
+                    metadata,
+                    confirmed,
+                ),
+                None => self.poll(
+                    connection,
+                    &proxy,
+                    tracklist.as_ref(),
+                    &player,
+                    &mut confirmed,
+                    &mut paused,
+                ),
+            };
+            if !playing {
+                self.let_go(&player, paused);
+                return Unfollow::Rediscover { played };
+            }
+            played = true;
+
+            // Sleep until the next poll, or until the followed player reports a change. A track
+            // waiting out `show_delay_ms` is checked again as soon as the delay is over.
+            let mut timeout = match &self.pending {
+                Some((_, since)) => self.show_delay.saturating_sub(since.elapsed()),
+                None => POLL_INTERVAL,
+            }
+            .min(POLL_INTERVAL);
+            loop {
+                let started = std::time::Instant::now();
+                match self.events_rx.recv_timeout(timeout) {
+                    Ok(WorkerEvent::PropertiesChanged { sender, changes })
+                        if owner.as_deref() == Some(sender.as_str()) =>
+                    {
+                        match changes.map_or(Reaction::Poll, |changes| self.react(&changes)) {
+                            Reaction::Poll => break,
+                            Reaction::Track(metadata) => {
+                                signalled = Some(metadata);
+                                break;
+                            }
+                            Reaction::Rate(rate) => {
+                                self.set_rate(&player, rate);
+                                timeout = timeout.saturating_sub(started.elapsed());
+                            }
+                            Reaction::Stopped { paused } => {
+                                self.let_go(&player, paused);
+                                return Unfollow::Rediscover { played };
+                            }
+                            Reaction::Ignore => {
+                                timeout = timeout.saturating_sub(started.elapsed());
+                            }
+                        }
+                    }
+                    Ok(WorkerEvent::Seeked { sender }) if owner.as_deref() == Some(&sender) => {
+                        break;
+                    }
+                    Ok(WorkerEvent::PropertiesChanged { sender, changes }) => {
+                        // Another player changed; note it for `most_recent`. One that started
+                        // playing may rank higher than this one, so ask discovery again.
+                        self.activity.insert(sender, Instant::now());
+                        let started_playing = changes.is_some_and(|c| c.started_playing());
+                        if started_playing && self.discovered(service_name) {
+                            return Unfollow::Switch;
+                        }
+                        timeout = timeout.saturating_sub(started.elapsed());
+                    }
+                    Ok(WorkerEvent::Seeked { sender }) => {
+                        self.activity.insert(sender, Instant::now());
+                        timeout = timeout.saturating_sub(started.elapsed());
+                    }
+                    Ok(WorkerEvent::Command(action)) => {
+                        self.execute(connection, service_name, action);
+                        break;
+                    }
+                    Ok(WorkerEvent::SetPlayer(player)) => {
+                        self.config.dbus_service = player;
+                        return Unfollow::Switch;
+                    }
+                    Ok(WorkerEvent::Seek(fraction)) => {
+                        if let Err(e) = self.seek(&proxy, fraction) {
+                            eprintln!("Failed to seek: {}", e);
+                        }
+                        break;
+                    }
+                    Ok(WorkerEvent::Refresh) => break,
+                    // The followed player quit, or handed its name to another process; the
+                    // proxy would only find the newcomer after the next poll.
+                    Ok(WorkerEvent::NameOwnerChanged { old_owner, new_owner })
+                        if owner.as_deref() == Some(old_owner.as_str()) =>
+                    {
+                        self.release_inhibitor();
+                        if new_owner.is_empty() {
+                            self.set_idle();
+                        }
+                        return Unfollow::Switch;
+                    }
+                    // A player starting up doesn't take over from the followed one.
+                    Ok(WorkerEvent::NameOwnerChanged { .. }) => {
+                        timeout = timeout.saturating_sub(started.elapsed());
+                    }
+                    Ok(WorkerEvent::ListPlayers(reply)) => {
+                        self.send_players(connection, reply);
+                        timeout = timeout.saturating_sub(started.elapsed());
+                    }
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => unreachable!("worker holds a sender"),
+                }
+            }
+        }
+    }
+
+    /// Whether `service_name` was picked by discovery, rather than named by `--player`,
+    /// `dbus_service`, `media_shim` or `strict_player`.
+    fn discovered(&self, service_name: &str) -> bool {
+        self.player_match.is_none()
+            && self.config.dbus_service.is_none()
+            && self.config.media_shim_name() != Some(service_name)
+            && !self.config.strict_player.unwrap_or(false)
+    }
+
+    /// Sorts a change of the followed player: a new track and a pause both matter, while
+    /// e.g. a volume change can go by without re-reading the player at all. Values the signal
+    /// carries are used as they are; the player is only polled for the ones it leaves out.
+    fn react(&self, changes: &PropertyChanges) -> Reaction {
+        if changes.interface != self.dbus.interface() {
+            return Reaction::Ignore;
+        }
+        let dbus = &self.dbus;
+        // A pause or stop says all there is to say, whatever else changed with it.
+        let status = changes.changed.get(dbus.status_property());
+        if let Some(status) = status.filter(|status| !is_playing(status)) {
+            return Reaction::Stopped { paused: is_paused(status) };
+        }
+        // Shown properties whose new value can't be taken from the signal: the title and
+        // artist properties, which are read alongside the metadata, and `CanSeek`.
+        let mut unread: Vec<&str> = [&dbus.title_property, &dbus.artist_property]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if self.read_position {
+            unread.push("CanSeek");
+        }
+        let mut shown = vec![dbus.status_property(), dbus.metadata_property()];
+        shown.extend(&unread);
+        if self.read_position {
+            shown.push("Rate");
+        }
+        // Invalidated properties come without their new value at all.
+        let invalidated = changes.invalidated.iter().any(|name| shown.contains(&name.as_str()));
+        if invalidated || changes.changed.keys().any(|name| unread.contains(&name.as_str())) {
+            return Reaction::Poll;
+        }
+        if let Some(metadata) = changes.changed.get(dbus.metadata_property()) {
+            if dbus.title_property.is_some() || dbus.artist_property.is_some() {
+                return Reaction::Poll;
+            }
+            return match metadata.try_clone().ok().and_then(|m| m.try_into().ok()) {
+                Some(metadata) => Reaction::Track(metadata),
+                None => Reaction::Poll,
+            };
+        }
+        match changes.changed.get("Rate").filter(|_| self.read_position) {
+            Some(rate) => match f64::try_from(rate) {
+                Ok(rate) => Reaction::Rate(rate),
+                Err(_) => Reaction::Poll,
+            },
+            // The player is only followed while it plays, so "Playing" is no news.
+            None => Reaction::Ignore,
+        }
+    }
+
+    /// Reads the player's status and metadata into the shared state. Returns `false` once the
+    /// player is no longer playing or can't be read. `confirmed` is set to whether the player
+    /// said it is playing, as opposed to having no status to say so, and `paused` to whether
+    /// it said it is paused.
+    fn poll(
+        &mut self,
+        connection: &Connection,
+        proxy: &Proxy,
+        tracklist: Option<&Proxy>,
+        player: &str,
+        confirmed: &mut bool,
+        paused: &mut bool,
+    ) -> bool {
+        // First, check the playback status. If not "Playing", stop following and re-run the
+        // discovery to find a new active player.
+        let assume_playing = self.config.assume_playing_without_status.unwrap_or(true);
+        let status = self.get_property::<OwnedValue>(proxy, self.dbus.status_property()).ok();
+        match resolve_status(status.as_deref().map(is_playing), assume_playing) {
+            Some(playing) => *confirmed = playing,
+            None => {
+                *paused = status.as_deref().is_some_and(is_paused);
+                return false;
+            }
+        }
+
+        match self.metadata(proxy) {
+            Ok(metadata) => self.show(connection, proxy, tracklist, player, metadata, *confirmed),
+            // This error means the player probably closed unexpectedly.
+            Err(_) => false,
+        }
+    }
+
+    /// Shows the track `metadata` describes. Returns `false` when there's nothing to show and
+    /// the player wasn't `confirmed` to be playing, so it is let go.
+    fn show(
+        &mut self,
+        connection: &Connection,
+        proxy: &Proxy,
+        tracklist: Option<&Proxy>,
+        player: &str,
+        metadata: HashMap<String, Value<'static>>,
+        confirmed: bool,
+    ) -> bool {
+        let title_key = mapped_key(&self.field_map, "title", "xesam:title");
+        let mut title = extract_string_metadata(&metadata, title_key).unwrap_or_default();
+        let mut artist = track_artist(&metadata, &self.field_map);
+        let mut fields = extract_metadata_fields(&metadata, &self.field_map);
+        if let Some((index, len)) = tracklist.and_then(|t| queue_position(t, &metadata)) {
+            fields.insert("queue_pos", (index + 1).to_string());
+            fields.insert("queue_len", len.to_string());
+        }
+        // Some players pad titles or double up spaces, which throws off the layout.
+        if self.config.normalize_whitespace.unwrap_or(true) {
+            title = collapse_whitespace(&title);
+            artist = collapse_whitespace(&artist);
+            if let Some(album) = fields.get_mut("album") {
+                *album = collapse_whitespace(album);
+            }
+        }
+
+        if !confirmed && title.is_empty() {
+            return false;
+        }
+        if let Some(inhibitor) = &self.inhibitor {
+            inhibitor.lock().unwrap().inhibit(connection);
+        }
+
+        // Hidden tracks (ads, usually) show as idle, but the player is still followed so
+        // the next track shows as soon as it starts.
+        if self.is_hidden(&title, &artist) {
+            self.set_current(None);
+            return true;
+        }
+
+        if let Some(scrobbler) = self.scrobbler.as_mut().filter(|_| !title.is_empty()) {
+            scrobbler.playing(Track {
+                id: metadata
+                    .get("mpris:trackid")
+                    .and_then(value_to_text)
+                    .unwrap_or_else(|| format!("{}\n{}\n{}", player, artist, title)),
+                artist: artist.clone(),
+                album: fields.get("album").cloned().unwrap_or_default(),
+                title: title.clone(),
+                number: metadata
+                    .get("xesam:trackNumber")
+                    .and_then(value_to_text)
+                    .unwrap_or_default(),
+                length: track_length(&metadata)
+                    .and_then(|micros| u64::try_from(micros).ok())
+                    .map(Duration::from_micros),
+            });
+        }
+        if !title.is_empty() {
+            let position = self
+                .read_position
+                .then(|| self.position(proxy, &metadata))
+                .flatten();
+            let left_off_at = self.resume.as_mut().zip(position.as_ref()).and_then(
+                |(resume, position)| {
+                    // Some players use one placeholder track ID for every track.
+                    let id = metadata
+                        .get("mpris:trackid")
+                        .and_then(value_to_text)
+                        .filter(|id| !id.ends_with("/NoTrack"))
+                        .unwrap_or_else(|| format!("{}\n{}", artist, title));
+                    resume.seen(&format!("{}\n{}", player, id), position.now_ms())
+                },
+            );
+            self.set_current(Some(NowPlaying {
+                title,
+                artist,
+                player: player.to_string(),
+                fields,
+                position,
+                lyrics: self
+                    .config
+                    .show_lyrics
+                    .unwrap_or(false)
+                    .then(|| extract_string_metadata(&metadata, "xesam:asText"))
+                    .flatten(),
+                art_url: self
+                    .art
+                    .as_ref()
+                    .and_then(|_| extract_string_metadata(&metadata, "mpris:artUrl")),
+                playing: true,
+                left_off_at,
+            }));
+        } else {
+            self.set_current(None);
+        }
+        true
+    }
+
+    /// Changes the shown track's playback rate, carrying on from where it has got to.
+    fn set_rate(&self, player: &str, rate: f64) {
+        let mut shared = self.shared.lock().unwrap();
+        let shown = shared.current.as_mut().filter(|now| now.player == player && now.playing);
+        if let Some(position) = shown.and_then(|now| now.position.as_mut()) {
+            position.micros = position.now_ms() * 1000;
+            position.at = Instant::now();
+            position.rate = rate;
+        }
+    }
+
+    /// Whether `hide_patterns` has a pattern found in the title or the artist.
+    fn is_hidden(&self, title: &str, artist: &str) -> bool {
+        if self.hide_patterns.is_empty() {
+            return false;
+        }
+        let (title, artist) = if self.hide_case_sensitive {
+            (title.to_string(), artist.to_string())
+        } else {
+            (title.to_lowercase(), artist.to_lowercase())
+        };
+        self.hide_patterns
+            .iter()
+            .any(|pattern| title.contains(pattern) || artist.contains(pattern))
+    }
+
+    /// Reads `Position` and `Rate`, timing the read so the position can be extrapolated from
+    /// the moment the player most likely answered.
+    fn position(&self, proxy: &Proxy, metadata: &HashMap<String, Value>) -> Option<Position> {
+        let before = Instant::now();
+        let micros = self.get_property::<i64>(proxy, "Position").ok()?;
+        let at = before + before.elapsed() / 2;
+        let rate = proxy.get_property::<f64>("Rate").unwrap_or(1.0);
+        Some(Position {
+            micros,
+            at,
+            rate,
+            length: track_length(metadata),
+            can_seek: proxy.get_property::<bool>("CanSeek").unwrap_or(false),
+        })
+    }
+
+    /// Reads the track's metadata, with the title and artist taken from properties of their own
+    /// if `[dbus]` says so.
+    fn metadata(&self, proxy: &Proxy) -> zbus::Result<HashMap<String, Value<'static>>> {
+        let dbus = &self.dbus;
+        let metadata = self.get_property::<HashMap<String, Value>>(proxy, dbus.metadata_property());
+        let mut metadata = match metadata {
+            // Services with only a title property have no metadata to read.
+            Err(_) if dbus.metadata_property.is_none() && dbus.title_property.is_some() => {
+                HashMap::new()
+            }
+            result => result?,
+        };
+        let properties = [
+            (&dbus.title_property, "title", "xesam:title"),
+            (&dbus.artist_property, "artist", "xesam:artist"),
+        ];
+        for (property, token, key) in properties {
+            if let Some(property) = property {
+                let value = self.get_property::<String>(proxy, property)?;
+                let key = mapped_key(&self.field_map, token, key);
+                metadata.insert(key.to_string(), Value::from(value));
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// Moves the current track to `fraction` of its length, if the player allows seeking. The
+    /// track is read through the same `[dbus]` proxy `SetPosition` is called on.
+    fn seek(&self, proxy: &Proxy, fraction: f32) -> Result<(), zbus::Error> {
+        if !self.get_property::<bool>(proxy, "CanSeek").unwrap_or(false) {
+            return Ok(());
+        }
+        let metadata = self.metadata(proxy)?;
+        // `SetPosition` is ignored unless it names the track that is playing.
+        let track_id = match metadata.get("mpris:trackid") {
+            Some(Value::ObjectPath(path)) => path.clone(),
+            Some(Value::Str(path)) => ObjectPath::try_from(path.as_str())?,
+            _ => return Ok(()),
+        };
+        let Some(length) = track_length(&metadata).filter(|length| *length > 0) else {
+            return Ok(());
+        };
+        let micros = (length as f64 * f64::from(fraction.clamp(0.0, 1.0))) as i64;
+        proxy
+            .call_method("SetPosition", &(track_id, micros))
+            .map(|_| ())
+    }
+
+    /// Reads a property, retrying transient failures (such as a timeout on a busy bus) up to
+    /// `property_retries` times so a single hiccup doesn't look like the player quitting.
+    fn get_property<T>(&self, proxy: &Proxy, name: &str) -> zbus::Result<T>
+    where
+        T: TryFrom<OwnedValue>,
+        T::Error: Into<zbus::Error>,
+    {
+        let retries = self.config.property_retries.unwrap_or(2);
+        let mut attempt = 0;
+        loop {
+            match proxy.get_property::<T>(name) {
+                Err(e) if attempt < retries && !is_permanent(&e) => {
+                    attempt += 1;
+                    thread::sleep(RETRY_DELAY);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn set_current(&mut self, current: Option<NowPlaying>) {
+        if let (Some(scrobbler), None) = (&mut self.scrobbler, &current) {
+            scrobbler.stopped();
+        }
+        if self.frozen.load(Ordering::Relaxed) {
+            // The frozen track stays, but still shows whether its player is playing it.
+            if let Some(shown) = &mut self.shared.lock().unwrap().current {
+                match current.filter(|now| track_key(now) == track_key(shown)) {
+                    Some(now) => *shown = now,
+                    None => shown.pause(),
+                }
+            }
+            return;
+        }
+        if let Some(now) = current.as_ref().filter(|_| !self.show_delay.is_zero()) {
+            let key = track_key(now);
+            let shown = self.shared.lock().unwrap().current.as_ref().map(track_key);
+            if shown.as_ref() != Some(&key) {
+                match &self.pending {
+                    Some((pending, since)) if *pending == key => {
+                        if since.elapsed() < self.show_delay {
+                            return;
+                        }
+                    }
+                    _ => {
+                        self.pending = Some((key, Instant::now()));
+                        return;
+                    }
+                }
+            }
+        }
+        self.pending = None;
+        if let Some(art) = &self.art {
+            art.update(current.as_ref().and_then(|now| now.art_url.as_deref()));
+        }
+        if let (Some(path), Some(now)) = (&self.state_file, &current) {
+            let content = sticky::serialize(now);
+            if self.saved.as_ref() != Some(&content) {
+                if let Err(e) = write_atomic(path, &content) {
+                    eprintln!("Failed to save last track to {}: {}", path.display(), e);
+                }
+                self.saved = Some(content);
+            }
+        }
+        self.shared.lock().unwrap().current = current;
+    }
+
+    /// Shows the `fallback_command`'s track now that no player is playing, or nothing.
+    fn set_idle(&mut self) {
+        let current = self.fallback_track();
+        self.set_current(current);
+    }
+
+    /// The `fallback_command`'s track, unless it is hidden.
+    fn fallback_track(&self) -> Option<NowPlaying> {
+        self.fallback
+            .as_ref()
+            .and_then(Fallback::current)
+            .filter(|now| !self.is_hidden(&now.title, &now.artist))
+    }
+
+    /// Stops following `player`. Its track stays shown as paused if it `paused`, unless the
+    /// `fallback_command` has a track to show instead.
+    fn let_go(&mut self, player: &str, paused: bool) {
+        self.release_inhibitor();
+        let fallback = self.fallback_track();
+        if !paused || fallback.is_some() {
+            return self.set_current(fallback);
+        }
+        self.pending = None;
+        if let Some(scrobbler) = &mut self.scrobbler {
+            scrobbler.stopped();
+        }
+        let mut shared = self.shared.lock().unwrap();
+        match shared.current.as_mut().filter(|now| now.player == player) {
+            Some(now) => now.pause(),
+            None => {
+                drop(shared);
+                self.set_current(None);
+            }
+        }
+    }
+
+    fn release_inhibitor(&self) {
+        if let Some(inhibitor) = &self.inhibitor {
+            inhibitor.lock().unwrap().release();
+        }
+    }
+
+    /// Waits for `timeout`, returning early on any event. Commands go to the player that was
+    /// followed last, so e.g. `playpause` can resume a paused player.
+    fn wait(&mut self, connection: &Connection, timeout: Duration) {
+        match self.events_rx.recv_timeout(timeout) {
+            Ok(WorkerEvent::Command(action)) => {
+                if let Some(service_name) = &self.followed {
+                    self.execute(connection, service_name, action);
+                }
+            }
+            Ok(WorkerEvent::SetPlayer(player)) => self.config.dbus_service = player,
+            Ok(WorkerEvent::ListPlayers(reply)) => self.send_players(connection, reply),
+            Ok(
+                WorkerEvent::PropertiesChanged { sender, .. } | WorkerEvent::Seeked { sender },
+            ) => {
+                self.activity.insert(sender, Instant::now());
+            }
+            _ => {}
+        }
+    }
+
+    /// Answers `ListPlayers` with every MPRIS player's bus name and identity.
+    fn send_players(&mut self, connection: &Connection, reply: Sender<Vec<(String, String)>>) {
+        let players = list_players(connection)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|name| {
+                let identity = self
+                    .identities
+                    .entry(name.clone())
+                    .or_insert_with(|| player_identity(connection, &name))
+                    .clone();
+                (name, identity)
+            })
+            .collect();
+        // The settings window may have closed in the meantime.
+        let _ = reply.send(players);
+    }
+
+    /// Runs `action`, handling the ones that need the current track rather than the player.
+    fn execute(&self, connection: &Connection, service_name: &str, action: Action) {
+        if let Action::Open = action {
+            let shared = self.shared.lock().unwrap();
+            let url = shared.current.as_ref().and_then(|current| current.fields.get("url"));
+            // Nothing to open for tracks without a URL.
+            if let Some(url) = url {
+                match Command::new("xdg-open").arg(url).spawn() {
+                    // Reap it in the background so it doesn't linger as a zombie.
+                    Ok(mut child) => {
+                        thread::spawn(move || child.wait());
+                    }
+                    Err(e) => eprintln!("Failed to open {}: {}", url, e),
+                }
+            }
+            return;
+        }
+        execute(connection, service_name, action);
+    }
+}
+
+/// Runs a player action against `service_name`.
+fn execute(connection: &Connection, service_name: &str, action: Action) {
+    let result = match action {
+        // `Raise` lives on the root interface rather than `.Player`.
+        Action::Raise => Proxy::new(
+            connection,
+            service_name,
+            "/org/mpris/MediaPlayer2",
+            "org.mpris.MediaPlayer2",
+        )
+        .and_then(|root| {
+            if root.get_property::<bool>("CanRaise").unwrap_or(false) {
+                root.call_method("Raise", &()).map(|_| ())
+            } else {
+                Ok(())
+            }
+        }),
+        _ => Proxy::new(
+            connection,
+            service_name,
+            "/org/mpris/MediaPlayer2",
+            "org.mpris.MediaPlayer2.Player",
+        )
+        .and_then(|proxy| execute_player_action(&proxy, action)),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to run player action: {}", e);
+    }
+}
+
+/// Runs an action on the `org.mpris.MediaPlayer2.Player` interface.
+fn execute_player_action(proxy: &Proxy, action: Action) -> Result<(), zbus::Error> {
+    match action {
+        Action::PlayPause => proxy.call_method("PlayPause", &()).map(|_| ()),
+        Action::Next => proxy.call_method("Next", &()).map(|_| ()),
+        Action::Previous => proxy.call_method("Previous", &()).map(|_| ()),
+        Action::VolumeUp | Action::VolumeDown => {
+            let step = if matches!(action, Action::VolumeUp) {
+                VOLUME_STEP
+            } else {
+                -VOLUME_STEP
+            };
+            proxy.get_property::<f64>("Volume").and_then(|volume| {
+                proxy
+                    .set_property("Volume", (volume + step).clamp(0.0, 1.0))
+                    .map_err(zbus::Error::from)
+            })
+        }
+        // Handled elsewhere.
+        Action::Raise
+        | Action::Open
+        | Action::Settings
+        | Action::Freeze
+        | Action::Copy
+        | Action::Quit => Ok(()),
+    }
+}
+
+/// The track's artist, the first one where there are several. Players may send no artist or
+/// an empty `xesam:artist` array, which leaves it empty; a title alone is still worth showing.
+fn track_artist(metadata: &HashMap<String, Value>, field_map: &HashMap<String, String>) -> String {
+    let key = mapped_key(field_map, "artist", "xesam:artist");
+    extract_artist_metadata(metadata, key).unwrap_or_default()
+}
+
+/// What makes a track a different one for `show_delay_ms`.
+fn track_key(now: &NowPlaying) -> String {
+    format!("{}\n{}\n{}", now.player, now.artist, now.title)
+}
+
+/// The track length in microseconds: `mpris:length`, or else `xesam:length`, which some players
+/// send instead, in seconds.
+fn track_length(metadata: &HashMap<String, Value>) -> Option<i64> {
+    if let Some(length) = metadata.get("mpris:length").and_then(length_value) {
+        return Some(length as i64);
+    }
+    let seconds = metadata.get("xesam:length").and_then(length_value)?;
+    Some((seconds * 1_000_000.0) as i64)
+}
+
+/// A length given as any number type, or as a string holding one, since players disagree.
+fn length_value(value: &Value) -> Option<f64> {
+    match value {
+        Value::I64(length) => Some(*length as f64),
+        Value::U64(length) => Some(*length as f64),
+        Value::I32(length) => Some((*length).into()),
+        Value::U32(length) => Some((*length).into()),
+        Value::I16(length) => Some((*length).into()),
+        Value::U16(length) => Some((*length).into()),
+        Value::F64(length) => Some(*length),
+        Value::Str(length) => length.trim().parse().ok(),
+        Value::Value(inner) => length_value(inner),
+        _ => None,
+    }
+    .filter(|length| length.is_finite() && *length >= 0.0)
+}
+
+/// Where the track is in the player's `TrackList`, as its index and the list's length. `None`
+/// for players without one, or when the track isn't in it.
+fn queue_position(tracklist: &Proxy, metadata: &HashMap<String, Value>) -> Option<(usize, usize)> {
+    let id = metadata.get("mpris:trackid").and_then(value_to_text)?;
+    let tracks = tracklist.get_property::<Vec<OwnedObjectPath>>("Tracks").ok()?;
+    let index = tracks.iter().position(|track| track.as_str() == id)?;
+    Some((index, tracks.len()))
+}
+
+/// Whether a status property says the player is playing: MPRIS's "Playing", or `true`.
+fn is_playing(status: &Value) -> bool {
+    match status {
+        Value::Str(status) => status.as_str() == "Playing",
+        Value::Bool(playing) => *playing,
+        Value::Value(inner) => is_playing(inner),
+        _ => false,
+    }
+}
+
+/// Whether a status property says the player is paused: MPRIS's "Paused", or `false`.
+fn is_paused(status: &Value) -> bool {
+    match status {
+        Value::Str(status) => status.as_str() == "Paused",
+        Value::Bool(playing) => !*playing,
+        Value::Value(inner) => is_paused(inner),
+        _ => false,
+    }
+}
+
+/// Whether a player with this status is still followed: `Some(true)` when it says it is
+/// playing, and `None` when it is paused or stopped. Some minimal players never implement
+/// `PlaybackStatus` (`status` is `None`); unless `assume_playing` is off, those are followed
+/// as `Some(false)`, for as long as their metadata has a title.
+fn resolve_status(status: Option<bool>, assume_playing: bool) -> Option<bool> {
+    match status {
+        Some(true) => Some(true),
+        None if assume_playing => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether a failed call means the player (or the property) is really gone, as opposed to a
+/// timeout or other transient failure worth retrying.
+fn is_permanent(error: &zbus::Error) -> bool {
+    match error {
+        zbus::Error::FDO(e) => matches!(
+            **e,
+            fdo::Error::ServiceUnknown(_)
+                | fdo::Error::NameHasNoOwner(_)
+                | fdo::Error::UnknownObject(_)
+                | fdo::Error::UnknownInterface(_)
+                | fdo::Error::UnknownProperty(_)
+                | fdo::Error::UnknownMethod(_)
+        ),
+        zbus::Error::MethodError(name, _, _) => matches!(
+            name.as_str(),
+            "org.freedesktop.DBus.Error.ServiceUnknown"
+                | "org.freedesktop.DBus.Error.NameHasNoOwner"
+                | "org.freedesktop.DBus.Error.UnknownObject"
+        ),
+        _ => false,
+    }
+}
+
+/// Returns the unique bus name currently owning `service_name`.
+fn name_owner(connection: &Connection, service_name: &str) -> Option<String> {
+    Proxy::new(
+        connection,
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+    )
+    .and_then(|proxy| proxy.call("GetNameOwner", &(service_name,)))
+    .ok()
+}
+
+/// Tells the worker whenever a player's bus name changes hands, so players starting up are
+/// found at once even while discovery is backed off, and ones quitting are let go at once.
+/// Watches only `dbus_service` if one is configured, otherwise every MPRIS name.
+fn spawn_player_watcher(
+    connection: &Connection,
+    dbus_service: Option<&str>,
+    events: Sender<WorkerEvent>,
+) {
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .sender("org.freedesktop.DBus")
+        .and_then(|b| b.interface("org.freedesktop.DBus"))
+        .and_then(|b| b.member("NameOwnerChanged"))
+        .and_then(|b| match dbus_service {
+            Some(name) => b.arg(0, name),
+            None => b.arg0ns("org.mpris.MediaPlayer2"),
+        })
+        .map(|b| b.build());
+    let iter = match rule.and_then(|rule| MessageIterator::for_match_rule(rule, connection, None)) {
+        Ok(iter) => iter,
+        Err(e) => {
+            eprintln!("Failed to watch for new players: {}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        for message in iter.flatten() {
+            let body = message.body();
+            let Ok((_, old_owner, new_owner)) = body.deserialize::<(&str, &str, &str)>() else {
+                continue;
+            };
+            let event = WorkerEvent::NameOwnerChanged {
+                old_owner: old_owner.to_string(),
+                new_owner: new_owner.to_string(),
+            };
+            if events.send(event).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Forwards every player's `interface.member` signal from `path` on `connection` to the worker.
+/// The thread ends when the connection goes away.
+fn spawn_signal_listener(
+    connection: &Connection,
+    path: &str,
+    interface: &str,
+    member: &str,
+    events: Sender<WorkerEvent>,
+) {
+    let seeked = member == "Seeked";
+    let properties = interface == "org.freedesktop.DBus.Properties";
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .interface(interface)
+        .and_then(|b| b.member(member))
+        .and_then(|b| b.path(path))
+        .map(|b| b.build());
+    let iter = match rule.and_then(|rule| MessageIterator::for_match_rule(rule, connection, None)) {
+        Ok(iter) => iter,
+        Err(e) => {
+            eprintln!("Failed to subscribe to player signals: {}. Polling only.", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        for message in iter.flatten() {
+            let header = message.header();
+            let Some(sender) = header.sender() else {
+                continue;
+            };
+            let sender = sender.to_string();
+            let event = if seeked {
+                WorkerEvent::Seeked { sender }
+            } else {
+                let changes = properties
+                    .then(|| message.body().deserialize().ok())
+                    .flatten()
+                    .map(|(interface, changed, invalidated)| PropertyChanges {
+                        interface,
+                        changed,
+                        invalidated,
+                    });
+                WorkerEvent::PropertiesChanged { sender, changes }
+            };
+            if events.send(event).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(config: Config) -> Worker {
+        let shared = Arc::new(Mutex::new(AppState {
+            current: None,
+            bus_error: None,
+        }));
+        Worker::new(shared, config, None, None, Arc::new(AtomicBool::new(false)))
+    }
+
+    fn changes(changed: Vec<(&str, Value)>, invalidated: &[&str]) -> PropertyChanges {
+        PropertyChanges {
+            interface: "org.mpris.MediaPlayer2.Player".to_string(),
+            changed: changed
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), OwnedValue::try_from(value).unwrap()))
+                .collect(),
+            invalidated: invalidated.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    fn metadata(title: &str) -> Value<'static> {
+        let mut metadata = HashMap::new();
+        metadata.insert("xesam:title".to_string(), Value::from(title.to_string()));
+        Value::from(metadata)
+    }
+
+    fn artist(value: Value) -> String {
+        let metadata = HashMap::from([("xesam:artist".to_string(), value)]);
+        track_artist(&metadata, &HashMap::new())
+    }
+
+    #[test]
+    fn artist_is_the_first_of_the_artists() {
+        assert_eq!(artist(Value::from(Vec::<String>::new())), "");
+        assert_eq!(artist(Value::from(vec!["Solo"])), "Solo");
+        assert_eq!(artist(Value::from(vec!["First", "Second"])), "First");
+        assert_eq!(artist(Value::from("Plain")), "Plain");
+        assert_eq!(track_artist(&HashMap::new(), &HashMap::new()), "");
+    }
+
+    #[test]
+    fn artist_follows_the_field_map() {
+        let metadata = HashMap::from([("custom:artist".to_string(), Value::from("Mapped"))]);
+        let field_map = HashMap::from([("artist".to_string(), "custom:artist".to_string())]);
+        assert_eq!(track_artist(&metadata, &field_map), "Mapped");
+    }
+
+    #[test]
+    fn length_values_of_any_number_type() {
+        let cases = [
+            (Value::from(240_000_000_i64), Some(240_000_000.0)),
+            (Value::from(240_000_000_u64), Some(240_000_000.0)),
+            (Value::from(240_i32), Some(240.0)),
+            (Value::from(" 240 "), Some(240.0)),
+            (Value::from("4:00"), None),
+            (Value::from(-1_i64), None),
+            (Value::from(true), None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(length_value(&value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn track_length_prefers_mpris_over_xesam_seconds() {
+        let cases = [
+            (vec![("mpris:length", Value::from(240_000_000_i64))], Some(240_000_000)),
+            (vec![("mpris:length", Value::from(240_000_000_u64))], Some(240_000_000)),
+            (vec![("xesam:length", Value::from(240_i32))], Some(240_000_000)),
+            (vec![("xesam:length", Value::from("240.5"))], Some(240_500_000)),
+            (
+                vec![
+                    ("mpris:length", Value::from(1_000_000_i64)),
+                    ("xesam:length", Value::from(240_i32)),
+                ],
+                Some(1_000_000),
+            ),
+            (
+                vec![
+                    ("mpris:length", Value::from("unknown")),
+                    ("xesam:length", Value::from(240_u64)),
+                ],
+                Some(240_000_000),
+            ),
+            (Vec::new(), None),
+        ];
+        for (entries, expected) in cases {
+            let metadata: HashMap<String, Value> = entries
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect();
+            assert_eq!(track_length(&metadata), expected, "{:?}", metadata);
+        }
+    }
+
+    /// An MPRIS player served in-process, to follow over a peer-to-peer connection.
+    struct FakePlayer {
+        status: &'static str,
+        set_positions: SetPositions,
+    }
+
+    /// Where `SetPosition` was asked to go, as track ID and microseconds.
+    type SetPositions = Arc<Mutex<Vec<(String, i64)>>>;
+
+    #[zbus::interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl FakePlayer {
+        #[zbus(property)]
+        fn playback_status(&self) -> String {
+            self.status.to_string()
+        }
+
+        #[zbus(property)]
+        fn metadata(&self) -> HashMap<String, OwnedValue> {
+            let track_id = ObjectPath::from_static_str_unchecked("/track/1");
+            [
+                ("mpris:trackid", Value::from(track_id)),
+                ("mpris:length", Value::from(200_000_000_i64)),
+                ("xesam:title", Value::from("  Fake   Song ")),
+                ("xesam:artist", Value::from(vec!["Fake Artist", "Guest"])),
+                ("xesam:album", Value::from("Fake Album")),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), OwnedValue::try_from(value).unwrap()))
+            .collect()
+        }
+
+        #[zbus(property)]
+        fn can_seek(&self) -> bool {
+            true
+        }
+
+        fn set_position(&self, track_id: ObjectPath<'_>, position: i64) {
+            self.set_positions.lock().unwrap().push((track_id.to_string(), position));
+        }
+    }
+
+    /// A connection to a [`FakePlayer`] with `status`, and where it records `SetPosition`
+    /// calls. The player's end of the connection is returned too, to keep it open.
+    fn fake_player(status: &'static str) -> (Connection, Connection, SetPositions) {
+        let (server, client) = std::os::unix::net::UnixStream::pair().unwrap();
+        let set_positions = SetPositions::default();
+        let player = FakePlayer {
+            status,
+            set_positions: Arc::clone(&set_positions),
+        };
+        // Both ends take part in the handshake, so one is built on a thread of its own.
+        let serving = thread::spawn(move || {
+            zbus::blocking::connection::Builder::unix_stream(server)
+                .server(zbus::Guid::generate())
+                .unwrap()
+                .p2p()
+                .serve_at("/org/mpris/MediaPlayer2", player)
+                .unwrap()
+                .build()
+                .unwrap()
+        });
+        let client = zbus::blocking::connection::Builder::unix_stream(client)
+            .p2p()
+            .build()
+            .unwrap();
+        (client, serving.join().unwrap(), set_positions)
+    }
+
+    const FAKE_NAME: &str = "org.mpris.MediaPlayer2.fake";
+
+    fn shown(worker: &Worker) -> Option<(String, String, Option<String>, bool)> {
+        let shared = worker.shared.lock().unwrap();
+        let now = shared.current.as_ref()?;
+        Some((now.title.clone(), now.artist.clone(), now.fields.get("album").cloned(), now.playing))
+    }
+
+    #[test]
+    fn following_a_playing_player_shows_its_track_and_seeks_it() {
+        let (connection, _player, set_positions) = fake_player("Playing");
+        let mut worker = worker(Config::default());
+        // Poll once, seek halfway through the track, then be told to follow another player.
+        for event in [
+            WorkerEvent::Refresh,
+            WorkerEvent::Seek(0.5),
+            WorkerEvent::SetPlayer(Some("other".to_string())),
+        ] {
+            worker.events_tx.send(event).unwrap();
+        }
+        assert!(matches!(worker.follow(&connection, FAKE_NAME), Unfollow::Switch));
+
+        let track = ("Fake Song".to_string(), "Fake Artist".to_string());
+        let album = Some("Fake Album".to_string());
+        assert_eq!(shown(&worker), Some((track.0, track.1, album, true)));
+        assert_eq!(*set_positions.lock().unwrap(), [("/track/1".to_string(), 100_000_000)]);
+        assert_eq!(worker.config.dbus_service.as_deref(), Some("other"));
+    }
+
+    #[test]
+    fn polling_a_paused_player_lets_it_go_but_keeps_its_track() {
+        let (connection, _player, _) = fake_player("Playing");
+        let mut worker = worker(Config::default());
+        worker.events_tx.send(WorkerEvent::SetPlayer(None)).unwrap();
+        worker.follow(&connection, FAKE_NAME);
+        assert!(shown(&worker).is_some_and(|(.., playing)| playing));
+
+        let (connection, _player, _) = fake_player("Paused");
+        let unfollow = worker.follow(&connection, FAKE_NAME);
+        assert!(matches!(unfollow, Unfollow::Rediscover { played: false }));
+        let kept = shown(&worker);
+        assert!(kept.is_some_and(|(title, .., playing)| title == "Fake Song" && !playing));
+
+        let (connection, _player, _) = fake_player("Stopped");
+        worker.follow(&connection, FAKE_NAME);
+        assert_eq!(shown(&worker), None);
+    }
+
+    #[test]
+    fn missing_status_is_assumed_playing_only_when_allowed() {
+        assert_eq!(resolve_status(None, true), Some(false));
+        assert_eq!(resolve_status(None, false), None);
+        for assume_playing in [true, false] {
+            assert_eq!(resolve_status(Some(true), assume_playing), Some(true));
+            assert_eq!(resolve_status(Some(false), assume_playing), None);
+        }
+    }
+
+    #[test]
+    fn new_metadata_is_taken_from_the_signal() {
+        let worker = worker(Config::default());
+        let changed = vec![
+            ("PlaybackStatus", Value::from("Playing")),
+            ("Metadata", metadata("Song")),
+        ];
+        match worker.react(&changes(changed, &[])) {
+            Reaction::Track(metadata) => {
+                assert_eq!(extract_string_metadata(&metadata, "xesam:title").unwrap(), "Song");
+            }
+            _ => panic!("expected the signalled track"),
+        }
+    }
+
+    #[test]
+    fn pausing_stops_following_whatever_else_changed() {
+        let worker = worker(Config::default());
+        let changed = vec![
+            ("PlaybackStatus", Value::from("Paused")),
+            ("Metadata", metadata("Song")),
+        ];
+        let reaction = worker.react(&changes(changed, &[]));
+        assert!(matches!(reaction, Reaction::Stopped { paused: true }));
+        let stopped = changes(vec![("PlaybackStatus", Value::from("Stopped"))], &[]);
+        assert!(matches!(worker.react(&stopped), Reaction::Stopped { paused: false }));
+    }
+
+    #[test]
+    fn letting_go_of_a_paused_player_keeps_its_track_as_paused() {
+        let mut worker = worker(Config::default());
+        let now = |player: &str| NowPlaying {
+            title: "Song".to_string(),
+            artist: String::new(),
+            player: player.to_string(),
+            fields: HashMap::new(),
+            position: None,
+            lyrics: None,
+            art_url: None,
+            playing: true,
+            left_off_at: None,
+        };
+        worker.set_current(Some(now("Player")));
+        worker.let_go("Player", true);
+        let shown = worker.shared.lock().unwrap().current.as_ref().map(|now| now.playing);
+        assert_eq!(shown, Some(false));
+
+        worker.let_go("Player", false);
+        assert!(worker.shared.lock().unwrap().current.is_none());
+
+        // Another player's track isn't this one's to keep.
+        worker.set_current(Some(now("Other")));
+        worker.let_go("Player", true);
+        assert!(worker.shared.lock().unwrap().current.is_none());
+    }
+
+    #[test]
+    fn playing_alone_and_unshown_properties_are_ignored() {
+        let worker = worker(Config::default());
+        let playing = changes(vec![("PlaybackStatus", Value::from("Playing"))], &[]);
+        assert!(matches!(worker.react(&playing), Reaction::Ignore));
+        let volume = changes(vec![("Volume", Value::from(0.5))], &[]);
+        assert!(matches!(worker.react(&volume), Reaction::Ignore));
+    }
+
+    #[test]
+    fn rate_is_applied_only_when_the_position_is_shown() {
+        let mut worker = worker(Config::default());
+        let rate = changes(vec![("Rate", Value::from(1.5))], &[]);
+        assert!(matches!(worker.react(&rate), Reaction::Ignore));
+        worker.read_position = true;
+        assert!(matches!(worker.react(&rate), Reaction::Rate(rate) if rate == 1.5));
+    }
+
+    #[test]
+    fn properties_without_a_value_fall_back_to_polling() {
+        let mut worker = worker(Config::default());
+        let invalidated = changes(Vec::new(), &["Metadata"]);
+        assert!(matches!(worker.react(&invalidated), Reaction::Poll));
+        worker.read_position = true;
+        let can_seek = changes(vec![("CanSeek", Value::from(true))], &[]);
+        assert!(matches!(worker.react(&can_seek), Reaction::Poll));
+    }
+
+    #[test]
+    fn metadata_is_polled_when_the_title_has_a_property_of_its_own() {
+        let mut worker = worker(Config::default());
+        worker.dbus.title_property = Some("Title".to_string());
+        let changed = changes(vec![("Metadata", metadata("Song"))], &[]);
+        assert!(matches!(worker.react(&changed), Reaction::Poll));
+    }
+
+    #[test]
+    fn other_interfaces_are_ignored() {
+        let worker = worker(Config::default());
+        let mut changed = changes(vec![("Metadata", metadata("Song"))], &[]);
+        changed.interface = "org.mpris.MediaPlayer2".to_string();
+        assert!(matches!(worker.react(&changed), Reaction::Ignore));
+    }
+}